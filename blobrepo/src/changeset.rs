@@ -4,12 +4,14 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::io::Write;
 
 use bytes::Bytes;
 use failure::FutureFailureErrorExt;
 use futures::future::{Either, Future, IntoFuture};
+use futures::stream;
+use futures_ext::{BoxStream, StreamExt};
 
 use blobstore::Blobstore;
 
@@ -254,3 +256,110 @@ impl Changeset for BlobChangeset {
         HgParents::new(self.content.p1(), self.content.p2())
     }
 }
+
+/// Walk the ancestors of `start` (including `start` itself) in breadth-first order, loading
+/// each changeset envelope from `blobstore` as it is reached. Each changeset is returned
+/// exactly once, even if it is reachable through several paths (e.g. across merges).
+pub fn walk_ancestors<B>(blobstore: B, start: HgNodeHash) -> BoxStream<HgChangesetEnvelope, Error>
+where
+    B: Blobstore + Clone,
+{
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    let mut visited = HashSet::new();
+    visited.insert(start);
+
+    stream::unfold((queue, visited), move |(mut queue, mut visited)| {
+        queue.pop_front().map(|node| {
+            let changesetid = HgChangesetId::new(node);
+            let key = changesetid.blobstore_key();
+
+            blobstore.get(key).and_then(move |got| {
+                let bytes = got.ok_or(ErrorKind::ChangesetMissing(changesetid))?;
+                let envelope = HgChangesetEnvelope::from_blob(bytes.into())?;
+                {
+                    let (p1, p2) = envelope.parents();
+                    for parent in p1.into_iter().chain(p2) {
+                        if visited.insert(*parent) {
+                            queue.push_back(*parent);
+                        }
+                    }
+                }
+                Ok((envelope, (queue, visited)))
+            })
+        })
+    }).boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::Stream;
+
+    use blobstore::EagerMemblob;
+
+    fn put_changeset(
+        blobstore: &EagerMemblob,
+        node_id: HgNodeHash,
+        p1: Option<HgNodeHash>,
+        p2: Option<HgNodeHash>,
+    ) {
+        let envelope = HgChangesetEnvelopeMut {
+            node_id,
+            p1,
+            p2,
+            contents: Bytes::from(format!("{}", node_id)),
+        }.freeze();
+        blobstore
+            .put(
+                HgChangesetId::new(node_id).blobstore_key(),
+                envelope.into_blob().into(),
+            )
+            .wait()
+            .expect("put should succeed");
+    }
+
+    #[test]
+    fn walk_ancestors_merge() {
+        //   d
+        //  / \
+        // b   c
+        //  \ /
+        //   a
+        let a = HgNodeHash::from_bytes(&[1; 20]).unwrap();
+        let b = HgNodeHash::from_bytes(&[2; 20]).unwrap();
+        let c = HgNodeHash::from_bytes(&[3; 20]).unwrap();
+        let d = HgNodeHash::from_bytes(&[4; 20]).unwrap();
+
+        let blobstore = EagerMemblob::new();
+        put_changeset(&blobstore, a, None, None);
+        put_changeset(&blobstore, b, Some(a), None);
+        put_changeset(&blobstore, c, Some(a), None);
+        put_changeset(&blobstore, d, Some(b), Some(c));
+
+        let visited: Vec<_> = walk_ancestors(blobstore, d)
+            .collect()
+            .wait()
+            .expect("walk should succeed")
+            .into_iter()
+            .map(|envelope| *envelope.node_id())
+            .collect();
+
+        assert_eq!(visited, vec![d, b, c, a]);
+    }
+
+    #[test]
+    fn walk_ancestors_missing() {
+        let a = HgNodeHash::from_bytes(&[1; 20]).unwrap();
+        let b = HgNodeHash::from_bytes(&[2; 20]).unwrap();
+
+        let blobstore = EagerMemblob::new();
+        put_changeset(&blobstore, b, Some(a), None);
+
+        walk_ancestors(blobstore, b)
+            .collect()
+            .wait()
+            .expect_err("unexpected OK - parent is missing");
+    }
+}
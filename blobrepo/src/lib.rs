@@ -68,7 +68,7 @@ mod utils;
 
 pub use errors::*;
 
-pub use changeset::BlobChangeset;
+pub use changeset::{walk_ancestors, BlobChangeset};
 pub use file::HgBlobEntry;
 pub use manifest::BlobManifest;
 pub use repo::{BlobRepo, ContentBlobInfo, ContentBlobMeta, CreateChangeset, UploadHgFileContents,
@@ -0,0 +1,226 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use bytes::Bytes;
+use failure::Error;
+use futures::{future, Future, Stream};
+use futures::future::Either;
+use futures_ext::{BoxFuture, FutureExt};
+
+use mononoke_types::BlobstoreBytes;
+use mononoke_types::hash::{Blake2, Context};
+
+use Blobstore;
+
+/// An identifier for a chunk of file contents stored by `DedupStore`. The chunk's bytes are
+/// stored as they are under `blobstore_key`.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+pub struct ChunkId(Blake2);
+
+impl ChunkId {
+    /// Compute the id of a chunk with the given contents.
+    pub fn from_data<T: AsRef<[u8]>>(data: T) -> Self {
+        let mut context = Context::new(b"chunk");
+        context.update(data);
+        ChunkId(context.finish())
+    }
+
+    #[inline]
+    pub fn blake2(&self) -> &Blake2 {
+        &self.0
+    }
+
+    /// Return a key suitable for blobstore use.
+    pub fn blobstore_key(&self) -> String {
+        format!("chunk.blake2.{}", self.0)
+    }
+}
+
+/// An identifier for a file stored as chunks by `DedupStore`. It's computed from the file's
+/// chunk ids, which are stored under `blobstore_key` as the concatenation of their digests.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+pub struct ChunkedFileId(Blake2);
+
+impl ChunkedFileId {
+    /// Compute the id of a file with the given chunks.
+    pub fn from_chunk_ids(chunk_ids: &[ChunkId]) -> Self {
+        let mut context = Context::new(b"chunkedfile");
+        context.update(chunk_list(chunk_ids));
+        ChunkedFileId(context.finish())
+    }
+
+    #[inline]
+    pub fn blake2(&self) -> &Blake2 {
+        &self.0
+    }
+
+    /// Return a key suitable for blobstore use.
+    pub fn blobstore_key(&self) -> String {
+        format!("chunkedfile.blake2.{}", self.0)
+    }
+}
+
+/// A layer over an existing blobstore that stores files as content-addressed chunks, so that
+/// chunks shared between files are only stored once.
+#[derive(Clone)]
+pub struct DedupStore<T: Blobstore + Clone> {
+    blobstore: T,
+}
+
+impl<T: Blobstore + Clone> DedupStore<T> {
+    pub fn new(blobstore: T) -> Self {
+        Self { blobstore }
+    }
+
+    /// Store each of `chunks` under its own `ChunkId`, skipping chunks that are already present,
+    /// and then the list of chunk ids under the file's `ChunkedFileId`. Returns the file id,
+    /// together with the chunk ids in order.
+    ///
+    /// Chunks are stored one at a time, so a chunk that appears twice in the same file is
+    /// also only stored once.
+    pub fn put_chunked<S>(&self, chunks: S) -> BoxFuture<(ChunkedFileId, Vec<ChunkId>), Error>
+    where
+        S: Stream<Item = Bytes, Error = Error> + Send + 'static,
+    {
+        let blobstore = self.blobstore.clone();
+        chunks
+            .and_then({
+                let blobstore = blobstore.clone();
+                move |chunk| {
+                    let id = ChunkId::from_data(&chunk);
+                    put_if_absent(blobstore.clone(), id.blobstore_key(), chunk).map(move |()| id)
+                }
+            })
+            .collect()
+            .and_then(move |chunk_ids| {
+                let file_id = ChunkedFileId::from_chunk_ids(&chunk_ids);
+                put_if_absent(blobstore, file_id.blobstore_key(), chunk_list(&chunk_ids))
+                    .map(move |()| (file_id, chunk_ids))
+            })
+            .boxify()
+    }
+}
+
+/// The concatenated digests of `chunk_ids`.
+fn chunk_list(chunk_ids: &[ChunkId]) -> Vec<u8> {
+    let mut list = Vec::with_capacity(chunk_ids.len() * 32);
+    for id in chunk_ids {
+        list.extend_from_slice(id.blake2().as_ref());
+    }
+    list
+}
+
+fn put_if_absent<T, B>(
+    blobstore: T,
+    key: String,
+    value: B,
+) -> impl Future<Item = (), Error = Error>
+where
+    T: Blobstore,
+    B: Into<Bytes>,
+{
+    let value = BlobstoreBytes::from_bytes(value);
+    blobstore.is_present(key.clone()).and_then(move |present| {
+        if present {
+            Either::A(future::ok(()))
+        } else {
+            Either::B(blobstore.put(key, value))
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::stream;
+
+    use mononoke_types::{FileContents, StoredContents};
+
+    use EagerMemblob;
+
+    /// A blobstore that counts how many puts it's been asked to do.
+    #[derive(Clone)]
+    struct PutCountingBlobstore {
+        blobstore: EagerMemblob,
+        puts: Arc<AtomicUsize>,
+    }
+
+    impl Blobstore for PutCountingBlobstore {
+        fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+            self.blobstore.get(key)
+        }
+
+        fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+            self.puts.fetch_add(1, Ordering::Relaxed);
+            self.blobstore.put(key, value)
+        }
+    }
+
+    /// Split `data` into 4-byte chunks, as it would be stored.
+    fn chunks(data: &'static str) -> Vec<Bytes> {
+        match FileContents::new_bytes(data).into_storage_form(0, 4) {
+            StoredContents::Chunked(chunks) => chunks,
+            StoredContents::Inline(_) => panic!("non-empty contents should be chunked"),
+        }
+    }
+
+    #[test]
+    fn shared_chunks_stored_once() {
+        let puts = Arc::new(AtomicUsize::new(0));
+        let blobstore = PutCountingBlobstore {
+            blobstore: EagerMemblob::new(),
+            puts: puts.clone(),
+        };
+        let store = DedupStore::new(blobstore.clone());
+
+        // This is EagerMemblob (immediate future completion) so calling wait() is fine.
+        // Two distinct chunks and the chunk list are stored.
+        let (first_id, first_chunks) = store
+            .put_chunked(stream::iter_ok(chunks("aaaabbbbaaaa")))
+            .wait()
+            .expect("put_chunked should succeed");
+        assert_eq!(first_chunks.len(), 3);
+        assert_eq!(first_chunks[0], first_chunks[2]);
+        assert_eq!(puts.load(Ordering::Relaxed), 3);
+
+        // "bbbb" is shared with the first file, so only "cccc" and the chunk list are new.
+        let (second_id, second_chunks) = store
+            .put_chunked(stream::iter_ok(chunks("bbbbcccc")))
+            .wait()
+            .expect("put_chunked should succeed");
+        assert_eq!(second_chunks[0], first_chunks[1]);
+        assert_eq!(puts.load(Ordering::Relaxed), 5);
+        assert_ne!(first_id, second_id);
+
+        let get = |key: String| {
+            blobstore
+                .get(key)
+                .wait()
+                .expect("get should succeed")
+                .expect("blob should be present")
+                .into_bytes()
+        };
+        for (id, data) in second_chunks.iter().zip(&["bbbb", "cccc"]) {
+            assert_eq!(get(id.blobstore_key()), Bytes::from(*data));
+        }
+        assert_eq!(
+            get(second_id.blobstore_key()),
+            Bytes::from(chunk_list(&second_chunks))
+        );
+
+        // Storing the same chunks again gives the same ids, and stores nothing.
+        let (again_id, again_chunks) = store
+            .put_chunked(stream::iter_ok(chunks("bbbbcccc")))
+            .wait()
+            .expect("put_chunked should succeed");
+        assert_eq!((again_id, again_chunks), (second_id, second_chunks));
+        assert_eq!(puts.load(Ordering::Relaxed), 5);
+    }
+}
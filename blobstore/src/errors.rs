@@ -4,9 +4,13 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::time::Duration;
+
 pub use failure::{Error, ResultExt};
 
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "Blob {} not found in blobstore", _0)] NotFound(String),
+    #[fail(display = "Blobstore {} of {} timed out after {:?}", _0, _1, _2)]
+    BlobstoreTimeout(&'static str, String, Duration),
 }
@@ -7,7 +7,6 @@
 #![deny(warnings)]
 
 extern crate asyncmemo;
-#[cfg(test)]
 extern crate bytes;
 #[macro_use]
 extern crate failure_ext as failure;
@@ -30,6 +29,7 @@ use std::sync::Arc;
 
 use failure::Error;
 use futures::{future, Future};
+use futures::future::Either;
 use futures_ext::{BoxFuture, FutureExt};
 
 use mononoke_types::BlobstoreBytes;
@@ -37,6 +37,9 @@ use mononoke_types::BlobstoreBytes;
 mod counted_blobstore;
 pub use counted_blobstore::CountedBlobstore;
 
+mod dedup;
+pub use dedup::{ChunkId, ChunkedFileId, DedupStore};
+
 mod in_memory_cache;
 pub use in_memory_cache::MemoizedBlobstore;
 
@@ -49,9 +52,23 @@ pub use memcache_cache::{MemcacheBlobstore, MemcacheBlobstoreExt};
 mod prefix;
 pub use prefix::PrefixBlobstore;
 
+mod timeout;
+pub use timeout::TimeoutBlobstore;
+
 mod errors;
 pub use errors::*;
 
+/// The result of `put_if_absent_or_equal`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PutOutcome {
+    /// The key was not present, and the value has been stored.
+    Inserted,
+    /// The key was already present with an identical value; nothing was written.
+    AlreadyPresentEqual,
+    /// The key was already present with a different value; nothing was written.
+    ConflictDiffers,
+}
+
 /// The blobstore interface, shared across all blobstores.
 /// A blobstore must provide the following guarantees:
 /// 1. `get` and `put` are atomic with respect to each other; a put will either put the entire
@@ -103,6 +120,44 @@ pub trait Blobstore: Send + Sync + 'static {
             })
             .boxify()
     }
+
+    /// Check which of `keys` are present, returning one `bool` per key in the same order. The
+    /// provided implementation calls `is_present` for each key; this can be overridden to check
+    /// many keys in one request.
+    fn filter_present(&self, keys: Vec<String>) -> BoxFuture<Vec<bool>, Error> {
+        future::join_all(keys.into_iter().map(|key| self.is_present(key))).boxify()
+    }
+}
+
+/// Store `value` under `key` in `blobstore` unless the key is already present. If it is, the
+/// existing value is compared with `value` instead of being overwritten, so that retried puts can
+/// tell whether the stored data is what they expect.
+///
+/// This takes the blobstore by value, since it's still needed once the existing value has been
+/// fetched, so it works with any blobstore including `Box<Blobstore>`. Pass a clone to keep using
+/// a shared blobstore.
+///
+/// Note that this is not atomic: a concurrent `put` between the check and the write can still
+/// be overwritten.
+pub fn put_if_absent_or_equal<B: Blobstore>(
+    blobstore: B,
+    key: String,
+    value: BlobstoreBytes,
+) -> BoxFuture<PutOutcome, Error> {
+    blobstore
+        .get(key.clone())
+        .and_then(move |existing| match existing {
+            Some(existing) => {
+                let outcome = if existing.as_bytes() == value.as_bytes() {
+                    PutOutcome::AlreadyPresentEqual
+                } else {
+                    PutOutcome::ConflictDiffers
+                };
+                Either::A(future::ok(outcome))
+            }
+            None => Either::B(blobstore.put(key, value).map(|()| PutOutcome::Inserted)),
+        })
+        .boxify()
 }
 
 impl Blobstore for Arc<Blobstore> {
@@ -118,6 +173,9 @@ impl Blobstore for Arc<Blobstore> {
     fn assert_present(&self, key: String) -> BoxFuture<(), Error> {
         self.as_ref().assert_present(key)
     }
+    fn filter_present(&self, keys: Vec<String>) -> BoxFuture<Vec<bool>, Error> {
+        self.as_ref().filter_present(keys)
+    }
 }
 
 impl Blobstore for Box<Blobstore> {
@@ -133,4 +191,7 @@ impl Blobstore for Box<Blobstore> {
     fn assert_present(&self, key: String) -> BoxFuture<(), Error> {
         self.as_ref().assert_present(key)
     }
+    fn filter_present(&self, keys: Vec<String>) -> BoxFuture<Vec<bool>, Error> {
+        self.as_ref().filter_present(keys)
+    }
 }
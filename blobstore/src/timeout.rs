@@ -0,0 +1,143 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::time::Duration;
+
+use failure::Error;
+use futures::Future;
+use futures_ext::{BoxFuture, FutureExt};
+use tokio::timer::DeadlineError;
+
+use mononoke_types::BlobstoreBytes;
+
+use {Blobstore, ErrorKind};
+
+/// A blobstore that fails any operation on the inner blobstore that doesn't complete within
+/// `timeout` with `ErrorKind::BlobstoreTimeout`. The inner operation is dropped when it times
+/// out.
+///
+/// This relies on the tokio timer, so operations must be run on a tokio runtime.
+#[derive(Clone)]
+pub struct TimeoutBlobstore<T: Blobstore> {
+    blobstore: T,
+    timeout: Duration,
+}
+
+impl<T: Blobstore> TimeoutBlobstore<T> {
+    pub fn new(blobstore: T, timeout: Duration) -> Self {
+        Self { blobstore, timeout }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.blobstore
+    }
+
+    pub fn as_inner(&self) -> &T {
+        &self.blobstore
+    }
+
+    fn with_timeout<F>(&self, op: &'static str, key: String, fut: F) -> BoxFuture<F::Item, Error>
+    where
+        F: Future<Error = Error> + Send + 'static,
+        F::Item: Send + 'static,
+    {
+        let timeout = self.timeout;
+        fut.timeout(timeout)
+            .map_err(move |err: DeadlineError<Error>| {
+                if err.is_elapsed() {
+                    ErrorKind::BlobstoreTimeout(op, key, timeout).into()
+                } else if err.is_inner() {
+                    err.into_inner().expect("inner error must be present")
+                } else {
+                    err.into_timer().expect("timer error must be present").into()
+                }
+            })
+            .boxify()
+    }
+}
+
+impl<T: Blobstore> Blobstore for TimeoutBlobstore<T> {
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let get = self.blobstore.get(key.clone());
+        self.with_timeout("get", key, get)
+    }
+
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        let put = self.blobstore.put(key.clone(), value);
+        self.with_timeout("put", key, put)
+    }
+
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        let is_present = self.blobstore.is_present(key.clone());
+        self.with_timeout("is_present", key, is_present)
+    }
+
+    fn assert_present(&self, key: String) -> BoxFuture<(), Error> {
+        let assert_present = self.blobstore.assert_present(key.clone());
+        self.with_timeout("assert_present", key, assert_present)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::future;
+    use tokio::runtime::Runtime;
+
+    use memblob::EagerMemblob;
+
+    /// A blobstore whose operations never complete.
+    struct HangingBlobstore;
+
+    impl Blobstore for HangingBlobstore {
+        fn get(&self, _key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+            future::empty().boxify()
+        }
+
+        fn put(&self, _key: String, _value: BlobstoreBytes) -> BoxFuture<(), Error> {
+            future::empty().boxify()
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut runtime = Runtime::new().expect("failed to create runtime");
+        let blobstore = TimeoutBlobstore::new(HangingBlobstore, Duration::from_millis(10));
+
+        let err = runtime
+            .block_on(blobstore.get("foo".to_string()))
+            .expect_err("unexpected OK - get should time out");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::BlobstoreTimeout("get", ref key, _)) if key == "foo" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let err = runtime
+            .block_on(blobstore.put("foo".to_string(), BlobstoreBytes::from_bytes("bar")))
+            .expect_err("unexpected OK - put should time out");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::BlobstoreTimeout("put", ref key, _)) if key == "foo" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_timeout() {
+        let mut runtime = Runtime::new().expect("failed to create runtime");
+        let blobstore = TimeoutBlobstore::new(EagerMemblob::new(), Duration::from_secs(10));
+
+        runtime
+            .block_on(blobstore.put("foo".to_string(), BlobstoreBytes::from_bytes("bar")))
+            .expect("put should succeed");
+        assert!(
+            runtime
+                .block_on(blobstore.get("foo".to_string()))
+                .expect("get should succeed")
+                .is_some()
+        );
+    }
+}
@@ -20,11 +20,13 @@ extern crate fileblob;
 extern crate mononoke_types;
 extern crate rocksblob;
 
+use std::sync::Arc;
+
 use bytes::Bytes;
 use futures::Future;
 use tempdir::TempDir;
 
-use blobstore::{Blobstore, EagerMemblob};
+use blobstore::{put_if_absent_or_equal, Blobstore, EagerMemblob, PutOutcome};
 use fileblob::Fileblob;
 use mononoke_types::BlobstoreBytes;
 use rocksblob::Rocksblob;
@@ -67,6 +69,69 @@ where
     assert_eq!(out.into_bytes(), Bytes::from_static(b"bar"));
 }
 
+fn filter_present<B>(blobstore: B)
+where
+    B: Blobstore,
+{
+    for key in &["a", "c"] {
+        blobstore
+            .put(key.to_string(), BlobstoreBytes::from_bytes(&b"value"[..]))
+            .wait()
+            .expect("put failed");
+    }
+
+    let keys = vec!["a", "b", "c", "d", "a"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let present = blobstore.filter_present(keys).wait().expect("filter_present failed");
+    assert_eq!(present, vec![true, false, true, false, true]);
+
+    let present = blobstore.filter_present(vec![]).wait().expect("filter_present failed");
+    assert!(present.is_empty());
+}
+
+fn put_if_absent_or_equal_outcomes<B>(blobstore: B)
+where
+    B: Blobstore,
+{
+    let blobstore: Arc<Blobstore> = Arc::new(blobstore);
+    let key = "key".to_string();
+
+    let outcome = put_if_absent_or_equal(
+        blobstore.clone(),
+        key.clone(),
+        BlobstoreBytes::from_bytes(&b"value"[..]),
+    );
+    assert_eq!(outcome.wait().expect("put failed"), PutOutcome::Inserted);
+
+    // Boxed blobstores work too.
+    let boxed: Box<Blobstore> = Box::new(blobstore.clone());
+    let outcome = put_if_absent_or_equal(
+        boxed,
+        key.clone(),
+        BlobstoreBytes::from_bytes(&b"value"[..]),
+    );
+    assert_eq!(
+        outcome.wait().expect("put failed"),
+        PutOutcome::AlreadyPresentEqual
+    );
+
+    let outcome = put_if_absent_or_equal(
+        blobstore.clone(),
+        key.clone(),
+        BlobstoreBytes::from_bytes(&b"other"[..]),
+    );
+    assert_eq!(
+        outcome.wait().expect("put failed"),
+        PutOutcome::ConflictDiffers
+    );
+
+    // The conflicting put must not have overwritten the original value.
+    let out = blobstore.get(key).wait().expect("get failed").expect("missing");
+    assert_eq!(out.into_bytes(), Bytes::from_static(b"value"));
+}
+
 macro_rules! blobstore_test_impl {
     ($mod_name: ident => {
         state: $state: expr,
@@ -93,6 +158,18 @@ macro_rules! blobstore_test_impl {
                 let state = $state;
                 boxable($new_cb(&state));
             }
+
+            #[test]
+            fn test_filter_present() {
+                let state = $state;
+                filter_present($new_cb(&state));
+            }
+
+            #[test]
+            fn test_put_if_absent_or_equal() {
+                let state = $state;
+                put_if_absent_or_equal_outcomes($new_cb(&state));
+            }
         }
     }
 }
@@ -7,7 +7,7 @@
 //! Envelopes used for Changeset nodes.
 
 use bytes::Bytes;
-use failure::{err_msg, SyncFailure};
+use failure::err_msg;
 use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use rust_thrift::compact_protocol;
@@ -17,6 +17,10 @@ use errors::*;
 use nodehash::HgNodeHash;
 use thrift;
 
+/// The smallest possible encoding of a changeset envelope: the node ID field (header, length
+/// and 20 bytes) followed by the struct stop byte.
+const MIN_ENCODED_SIZE: usize = 23;
+
 /// A mutable representation of a Mercurial file node.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct HgChangesetEnvelopeMut {
@@ -30,6 +34,23 @@ impl HgChangesetEnvelopeMut {
     pub fn freeze(self) -> HgChangesetEnvelope {
         HgChangesetEnvelope { inner: self }
     }
+
+    /// Put the parents into the form Mercurial expects: a changeset with a single parent
+    /// stores it in `p1`, with `p2` empty. Some tools produce changesets with only `p2` set;
+    /// this moves such a parent into `p1`.
+    ///
+    /// When both parents are present their order is significant in Mercurial, so they are
+    /// left alone.
+    ///
+    /// Returns `true` if the parents were changed.
+    pub fn canonicalize_parents(&mut self) -> bool {
+        if self.p1.is_none() && self.p2.is_some() {
+            self.p1 = self.p2.take();
+            true
+        } else {
+            false
+        }
+    }
 }
 
 /// A serialized representation of a Mercurial Changeset node in the blob store.
@@ -61,12 +82,12 @@ impl HgChangesetEnvelope {
     }
 
     pub fn from_blob(blob: HgEnvelopeBlob) -> Result<Self> {
-        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
-        let thrift_tc = compact_protocol::deserialize(blob.0.as_ref())
-            .map_err(SyncFailure::new)
-            .context(ErrorKind::BlobDeserializeError(
-                "HgChangesetEnvelope".into(),
-            ))?;
+        let thrift_tc = match compact_protocol::deserialize(blob.0.as_ref()) {
+            Ok(thrift_tc) => thrift_tc,
+            Err(err) => {
+                return Err(blob.deserialize_error(err, "HgChangesetEnvelope", MIN_ENCODED_SIZE));
+            }
+        };
         Self::from_thrift(thrift_tc)
     }
 
@@ -136,6 +157,8 @@ impl Arbitrary for HgChangesetEnvelope {
 mod test {
     use super::*;
 
+    use failure::Context;
+
     quickcheck! {
         fn thrift_roundtrip(ce: HgChangesetEnvelope) -> bool {
             let thrift_ce = ce.clone().into_thrift();
@@ -152,6 +175,85 @@ mod test {
         }
     }
 
+    #[test]
+    fn truncated_blob() {
+        let ce = HgChangesetEnvelopeMut {
+            node_id: HgNodeHash::from_bytes(&[1; 20]).unwrap(),
+            p1: Some(HgNodeHash::from_bytes(&[2; 20]).unwrap()),
+            p2: None,
+            contents: Bytes::from(&b"abc"[..]),
+        }.freeze();
+        let blob = ce.into_blob();
+
+        for len in &[0, 10, blob.0.len() / 2, blob.0.len() - 1] {
+            let truncated = HgEnvelopeBlob(blob.0.slice_to(*len));
+            let err = HgChangesetEnvelope::from_blob(truncated)
+                .expect_err("unexpected OK - truncated blob");
+            match err.downcast::<Context<ErrorKind>>() {
+                Ok(ctxt) => match ctxt.get_context() {
+                    &ErrorKind::TruncatedBlob { expected_min, got, .. } => {
+                        assert_eq!(expected_min, MIN_ENCODED_SIZE);
+                        assert_eq!(got, *len);
+                    }
+                    bad => panic!("unexpected error {}", bad),
+                },
+                Err(bad) => panic!("other error: {:?}", bad),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_blob() {
+        let ce = HgChangesetEnvelopeMut {
+            node_id: HgNodeHash::from_bytes(&[1; 20]).unwrap(),
+            p1: None,
+            p2: None,
+            contents: Bytes::from(&b"abc"[..]),
+        }.freeze();
+        // Give the first field header an invalid compact protocol type (13). The blob is still
+        // complete, so this isn't a truncation.
+        let mut bytes = ce.into_blob().0.to_vec();
+        bytes[0] = (bytes[0] & 0xf0) | 13;
+        let corrupt = HgEnvelopeBlob(Bytes::from(bytes));
+
+        let err =
+            HgChangesetEnvelope::from_blob(corrupt).expect_err("unexpected OK - corrupt blob");
+        match err.downcast::<Context<ErrorKind>>() {
+            Ok(ctxt) => match ctxt.get_context() {
+                &ErrorKind::BlobDeserializeError(ref name) => {
+                    assert_eq!(name, "HgChangesetEnvelope")
+                }
+                bad => panic!("unexpected error {}", bad),
+            },
+            Err(bad) => panic!("other error: {:?}", bad),
+        }
+    }
+
+    #[test]
+    fn canonicalize_parents() {
+        let p = HgNodeHash::from_bytes(&[2; 20]).unwrap();
+        let mut ce = HgChangesetEnvelopeMut {
+            node_id: HgNodeHash::from_bytes(&[1; 20]).unwrap(),
+            p1: None,
+            p2: Some(p),
+            contents: Bytes::from(&b"abc"[..]),
+        };
+
+        assert!(ce.canonicalize_parents());
+        assert_eq!(ce.p1, Some(p));
+        assert_eq!(ce.p2, None);
+
+        // Already canonical, so nothing should change.
+        let before = ce.clone();
+        assert!(!ce.canonicalize_parents());
+        assert_eq!(ce, before);
+
+        ce.p2 = Some(HgNodeHash::from_bytes(&[3; 20]).unwrap());
+        let before = ce.clone();
+        assert!(!ce.canonicalize_parents());
+        assert_eq!(ce, before);
+    }
+
     #[test]
     fn bad_thrift() {
         let mut thrift_ce = thrift::HgChangesetEnvelope {
@@ -7,7 +7,7 @@
 //! Envelopes used for file nodes.
 
 use bytes::Bytes;
-use failure::{err_msg, SyncFailure};
+use failure::err_msg;
 use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use rust_thrift::compact_protocol;
@@ -19,6 +19,10 @@ use errors::*;
 use nodehash::HgNodeHash;
 use thrift;
 
+/// The smallest possible encoding of a file envelope: the node ID field (header, length and 20
+/// bytes), the content size field (header and at least one byte) and the struct stop byte.
+const MIN_ENCODED_SIZE: usize = 25;
+
 /// A mutable representation of a Mercurial file node.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct HgFileEnvelopeMut {
@@ -66,10 +70,12 @@ impl HgFileEnvelope {
     }
 
     pub fn from_blob(blob: HgEnvelopeBlob) -> Result<Self> {
-        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
-        let thrift_tc = compact_protocol::deserialize(blob.0.as_ref())
-            .map_err(SyncFailure::new)
-            .context(ErrorKind::BlobDeserializeError("HgFileEnvelope".into()))?;
+        let thrift_tc = match compact_protocol::deserialize(blob.0.as_ref()) {
+            Ok(thrift_tc) => thrift_tc,
+            Err(err) => {
+                return Err(blob.deserialize_error(err, "HgFileEnvelope", MIN_ENCODED_SIZE));
+            }
+        };
         Self::from_thrift(thrift_tc)
     }
 
@@ -7,7 +7,7 @@
 //! Envelopes used for manifest nodes.
 
 use bytes::Bytes;
-use failure::{err_msg, SyncFailure};
+use failure::err_msg;
 use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use rust_thrift::compact_protocol;
@@ -17,6 +17,10 @@ use errors::*;
 use nodehash::HgNodeHash;
 use thrift;
 
+/// The smallest possible encoding of a manifest envelope: the node ID and computed node ID
+/// fields (header, length and 20 bytes each) followed by the struct stop byte.
+const MIN_ENCODED_SIZE: usize = 45;
+
 /// A mutable representation of a Mercurial file node.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct HgManifestEnvelopeMut {
@@ -63,10 +67,12 @@ impl HgManifestEnvelope {
     }
 
     pub fn from_blob(blob: HgEnvelopeBlob) -> Result<Self> {
-        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
-        let thrift_tc = compact_protocol::deserialize(blob.0.as_ref())
-            .map_err(SyncFailure::new)
-            .context(ErrorKind::BlobDeserializeError("HgManifestEnvelope".into()))?;
+        let thrift_tc = match compact_protocol::deserialize(blob.0.as_ref()) {
+            Ok(thrift_tc) => thrift_tc,
+            Err(err) => {
+                return Err(blob.deserialize_error(err, "HgManifestEnvelope", MIN_ENCODED_SIZE));
+            }
+        };
         Self::from_thrift(thrift_tc)
     }
 
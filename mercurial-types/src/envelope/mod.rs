@@ -14,13 +14,63 @@ pub use self::changeset_envelope::{HgChangesetEnvelope, HgChangesetEnvelopeMut};
 pub use self::file_envelope::{HgFileEnvelope, HgFileEnvelopeMut};
 pub use self::manifest_envelope::{HgManifestEnvelope, HgManifestEnvelopeMut};
 
+use std::error::Error as StdError;
+use std::io;
+
 use mononoke_types::BlobstoreBytes;
 
 use bytes::Bytes;
+use failure::SyncFailure;
+use futures::Stream;
+use futures_ext::{BoxStream, StreamExt};
+
+use errors::*;
 
 #[derive(Clone, Debug)]
 pub struct HgEnvelopeBlob(Bytes);
 
+impl HgEnvelopeBlob {
+    /// Classify a failure to deserialize this blob as an envelope called `name`, whose
+    /// smallest valid encoding is `expected_min` bytes long.
+    ///
+    /// A blob that was cut short is reported as `ErrorKind::TruncatedBlob`, so that callers can
+    /// choose to retry the fetch; anything else is reported as `ErrorKind::BlobDeserializeError`.
+    /// A blob is considered truncated if deserializing it ran out of input, i.e. if `err` or one
+    /// of its causes is an I/O error of kind `UnexpectedEof`.
+    pub(crate) fn deserialize_error<E>(&self, err: E, name: &str, expected_min: usize) -> Error
+    where
+        E: StdError + Send + 'static,
+    {
+        let truncated = is_unexpected_eof(&err);
+        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
+        let err = Error::from(SyncFailure::new(err));
+        if truncated {
+            err.context(ErrorKind::TruncatedBlob {
+                name: name.into(),
+                expected_min,
+                got: self.0.len(),
+            }).into()
+        } else {
+            err.context(ErrorKind::BlobDeserializeError(name.into()))
+                .into()
+        }
+    }
+}
+
+/// Whether `err` or any of its causes is an I/O error for input that ended early.
+fn is_unexpected_eof(err: &(StdError + 'static)) -> bool {
+    let mut cur = Some(err);
+    while let Some(err) = cur {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                return true;
+            }
+        }
+        cur = err.source();
+    }
+    false
+}
+
 impl From<BlobstoreBytes> for HgEnvelopeBlob {
     #[inline]
     fn from(bytes: BlobstoreBytes) -> HgEnvelopeBlob {
@@ -34,3 +84,73 @@ impl From<HgEnvelopeBlob> for BlobstoreBytes {
         BlobstoreBytes::from_bytes(blob.0)
     }
 }
+
+/// Parse a stream of changeset envelope blobs, e.g. while scanning a blobstore for repair. Each
+/// blob's key is paired with either the parsed envelope or the error from parsing it, so a
+/// corrupt blob doesn't end the scan. Errors from the input stream itself still end the stream.
+pub fn scan_envelopes<S>(
+    blobs: S,
+) -> BoxStream<::std::result::Result<(String, HgChangesetEnvelope), (String, Error)>, Error>
+where
+    S: Stream<Item = (String, HgEnvelopeBlob), Error = Error> + Send + 'static,
+{
+    blobs
+        .map(|(key, blob)| match HgChangesetEnvelope::from_blob(blob) {
+            Ok(envelope) => Ok((key, envelope)),
+            Err(err) => Err((key, err)),
+        })
+        .boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::{stream, Future};
+
+    use nodehash::HgNodeHash;
+
+    #[test]
+    fn scan_envelopes_recovers() {
+        let envelope = HgChangesetEnvelopeMut {
+            node_id: HgNodeHash::from_bytes(&[1; 20]).unwrap(),
+            p1: None,
+            p2: None,
+            contents: Bytes::from(&b"abc"[..]),
+        }.freeze();
+        let blobs = vec![
+            ("good1".to_string(), envelope.clone().into_blob()),
+            ("corrupt".to_string(), HgEnvelopeBlob(Bytes::from(&b"garbage"[..]))),
+            ("good2".to_string(), envelope.clone().into_blob()),
+            ("empty".to_string(), HgEnvelopeBlob(Bytes::new())),
+        ];
+
+        let results = scan_envelopes(stream::iter_ok(blobs))
+            .collect()
+            .wait()
+            .expect("scan should not fail");
+        assert_eq!(results.len(), 4);
+        match results[0] {
+            Ok((ref key, ref parsed)) => {
+                assert_eq!(key, "good1");
+                assert_eq!(parsed, &envelope);
+            }
+            Err((ref key, ref err)) => panic!("unexpected error for {}: {}", key, err),
+        }
+        match results[1] {
+            Err((ref key, _)) => assert_eq!(key, "corrupt"),
+            Ok((ref key, _)) => panic!("unexpected OK for {}", key),
+        }
+        match results[2] {
+            Ok((ref key, ref parsed)) => {
+                assert_eq!(key, "good2");
+                assert_eq!(parsed, &envelope);
+            }
+            Err((ref key, ref err)) => panic!("unexpected error for {}: {}", key, err),
+        }
+        match results[3] {
+            Err((ref key, _)) => assert_eq!(key, "empty"),
+            Ok((ref key, _)) => panic!("unexpected OK for {}", key),
+        }
+    }
+}
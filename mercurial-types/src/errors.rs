@@ -9,9 +9,17 @@ pub use failure::{Error, ResultExt};
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "invalid sha-1 input: {}", _0)] InvalidSha1Input(String),
+    #[fail(display = "invalid sha-256 input: {}", _0)] InvalidSha256Input(String),
     #[fail(display = "invalid fragment list: {}", _0)] InvalidFragmentList(String),
     #[fail(display = "invalid Thrift structure '{}': {}", _0, _1)] InvalidThrift(String, String),
     #[fail(display = "error while deserializing blob for '{}'", _0)] BlobDeserializeError(String),
+    #[fail(display = "truncated blob for '{}': expected at least {} bytes, got {}", name,
+           expected_min, got)]
+    TruncatedBlob {
+        name: String,
+        expected_min: usize,
+        got: usize,
+    },
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -11,6 +11,7 @@ use ascii::{AsciiStr, AsciiString};
 use quickcheck::{single_shrinker, Arbitrary, Gen};
 use rust_crypto::digest::Digest;
 use rust_crypto::sha1;
+use rust_crypto::sha2;
 
 use thrift;
 
@@ -68,6 +69,16 @@ impl Sha1 {
         Self::from_str(s.as_str())
     }
 
+    /// Compare with `other` in constant time, for when the result gates access to something and
+    /// `==` (which stops at the first differing byte) could leak timing information.
+    pub fn ct_eq(&self, other: &Self) -> bool {
+        let diff = self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        diff == 0
+    }
+
     pub fn to_hex(&self) -> AsciiString {
         let mut v = Vec::with_capacity(40);
         for &byte in self.as_ref() {
@@ -183,6 +194,121 @@ impl Context {
     }
 }
 
+/// Raw SHA-256 hash
+///
+/// Mercurial doesn't use SHA-256; this is for interoperating with stores that are keyed by it.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Serialize, Deserialize, HeapSizeOf)]
+pub struct Sha256([u8; 32]);
+
+impl Sha256 {
+    /// Construct a `Sha256` from an array of 32 bytes containing a
+    /// SHA-256 (ie, *not* a hash of the bytes).
+    pub fn from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<Sha256> {
+        let bytes = bytes.as_ref();
+        if bytes.len() != 32 {
+            bail_err!(ErrorKind::InvalidSha256Input("need exactly 32 bytes".into()));
+        } else {
+            let mut ret = Sha256([0; 32]);
+            ret.0[..].copy_from_slice(bytes);
+            Ok(ret)
+        }
+    }
+
+    /// Construct a `Sha256` from an array of 32 bytes.
+    #[inline]
+    pub const fn from_byte_array(arr: [u8; 32]) -> Sha256 {
+        Sha256(arr)
+    }
+
+    /// Construct a `Sha256` from a hex-encoded `AsciiStr`.
+    #[inline]
+    pub fn from_ascii_str(s: &AsciiStr) -> Result<Sha256> {
+        Self::from_str(s.as_str())
+    }
+
+    pub fn to_hex(&self) -> AsciiString {
+        let mut v = Vec::with_capacity(64);
+        for &byte in self.as_ref() {
+            v.push(HEX_CHARS[(byte >> 4) as usize]);
+            v.push(HEX_CHARS[(byte & 0xf) as usize]);
+        }
+
+        unsafe {
+            // A hex string is always a pure ASCII string.
+            AsciiString::from_ascii_unchecked(v)
+        }
+    }
+}
+
+/// Compute the `Sha256` for a slice of bytes.
+pub fn sha256(data: &[u8]) -> Sha256 {
+    let mut sha256 = sha2::Sha256::new();
+    sha256.input(data);
+
+    let mut ret = Sha256([0; 32]);
+    sha256.result(&mut ret.0[..]);
+    ret
+}
+
+/// Get a reference to the underlying bytes of a `Sha256`
+impl AsRef<[u8]> for Sha256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl FromStr for Sha256 {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Sha256> {
+        if s.len() != 64 {
+            bail_err!(ErrorKind::InvalidSha256Input(
+                "need exactly 64 hex digits".into()
+            ));
+        }
+        if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail_err!(ErrorKind::InvalidSha256Input("bad digit".into()));
+        }
+
+        let mut ret = Sha256([0; 32]);
+
+        for idx in 0..ret.0.len() {
+            ret.0[idx] = match u8::from_str_radix(&s[(idx * 2)..(idx * 2 + 2)], 16) {
+                Ok(v) => v,
+                Err(_) => bail_err!(ErrorKind::InvalidSha256Input("bad digit".into())),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+impl Display for Sha256 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.to_hex(), fmt)
+    }
+}
+
+/// Custom `Debug` output for `Sha256` so it prints in hex.
+impl Debug for Sha256 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Sha256({})", self)
+    }
+}
+
+impl Arbitrary for Sha256 {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut bytes = [0; 32];
+        g.fill_bytes(&mut bytes);
+        Sha256(bytes)
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        single_shrinker(Sha256([0; 32]))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use quickcheck::TestResult;
@@ -264,6 +390,44 @@ mod test {
         };
     }
 
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    const SHA256_NILHASH: Sha256 = Sha256([0xe3, 0xb0, 0xc4, 0x42,
+                                           0x98, 0xfc, 0x1c, 0x14,
+                                           0x9a, 0xfb, 0xf4, 0xc8,
+                                           0x99, 0x6f, 0xb9, 0x24,
+                                           0x27, 0xae, 0x41, 0xe4,
+                                           0x64, 0x9b, 0x93, 0x4c,
+                                           0xa4, 0x95, 0x99, 0x1b,
+                                           0x78, 0x52, 0xb8, 0x55]);
+
+    #[test]
+    fn sha256_nil() {
+        assert_eq!(sha256(&[]), SHA256_NILHASH);
+        assert_eq!(
+            format!("{}", SHA256_NILHASH),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn sha256_parse() {
+        assert_eq!(
+            SHA256_NILHASH,
+            Sha256::from_str("E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855")
+                .unwrap()
+        );
+        Sha256::from_str("").expect_err("unexpected OK - zero len");
+        Sha256::from_str("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b85")
+            .expect_err("unexpected OK - trunc");
+        Sha256::from_str("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b8550")
+            .expect_err("unexpected OK - too long");
+        Sha256::from_str("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b85x")
+            .expect_err("unexpected OK - badchar end");
+        Sha256::from_str("+3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+            .expect_err("unexpected OK - sign");
+        Sha256::from_bytes(&[0; 31]).expect_err("unexpected OK - too short");
+    }
+
     #[test]
     fn parse_thrift() {
         let null_thrift = thrift::Sha1(vec![0; 20]);
@@ -301,10 +465,27 @@ mod test {
             h == sh
         }
 
+        fn ct_eq_matches_eq(h: Sha1, other: Sha1, idx: usize, flip: u8) -> bool {
+            let mut flipped = h;
+            flipped.0[idx % 20] ^= flip;
+            h.ct_eq(&h) && h.ct_eq(&other) == (h == other)
+                && h.ct_eq(&flipped) == (h == flipped)
+        }
+
         fn to_hex_roundtrip(h: Sha1) -> bool {
             let v = h.to_hex();
             let sh = Sha1::from_ascii_str(&v).unwrap();
             h == sh
         }
+
+        fn sha256_to_hex_roundtrip(h: Sha256) -> bool {
+            let v = h.to_hex();
+            let sh = Sha256::from_ascii_str(&v).unwrap();
+            h == sh
+        }
+
+        fn sha256_bytes_roundtrip(h: Sha256) -> bool {
+            Sha256::from_bytes(h.as_ref()).unwrap() == h
+        }
     }
 }
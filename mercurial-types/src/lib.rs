@@ -101,8 +101,8 @@ pub use blob::{HgBlob, HgBlobHash};
 pub use blobnode::{HgBlobNode, HgParents};
 pub use changeset::Changeset;
 pub use delta::Delta;
-pub use envelope::{HgChangesetEnvelope, HgChangesetEnvelopeMut, HgFileEnvelope, HgFileEnvelopeMut,
-                   HgManifestEnvelope, HgManifestEnvelopeMut};
+pub use envelope::{scan_envelopes, HgChangesetEnvelope, HgChangesetEnvelopeMut, HgEnvelopeBlob,
+                   HgFileEnvelope, HgFileEnvelopeMut, HgManifestEnvelope, HgManifestEnvelopeMut};
 pub use fsencode::{fncache_fsencode, simple_fsencode};
 pub use manifest::{Entry, Manifest, Type};
 pub use node::Node;
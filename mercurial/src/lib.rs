@@ -13,6 +13,7 @@ extern crate ascii;
 extern crate bytes;
 extern crate flate2;
 extern crate futures;
+extern crate futures_cpupool;
 #[macro_use]
 extern crate futures_ext;
 
@@ -59,6 +60,8 @@ pub mod stockbookmarks;
 pub mod symlink;
 mod errors;
 pub use errors::*;
+mod validate;
+pub use validate::validate_paths_parallel;
 
 pub use changeset::RevlogChangeset;
 pub use manifest::{EntryContent, RevlogEntry};
@@ -0,0 +1,118 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::cmp;
+
+use futures::Future;
+use futures::future::join_all;
+use futures_cpupool::CpuPool;
+use futures_ext::{BoxFuture, FutureExt};
+
+use mononoke_types::path::check_pcf;
+
+use errors::*;
+use MPath;
+
+/// Validate a large set of paths in parallel on `pool`, then check that the valid paths are
+/// path-conflict-free. The paths are split into at most `concurrency` chunks, so at most that
+/// many of the pool's threads are used at once.
+///
+/// If any path is invalid, the result is `Ok(Err(errors))` with the error for each invalid path,
+/// keyed by its index in `paths` and sorted by index. Otherwise the result is the sorted list of
+/// paths, or an error if they aren't path-conflict-free.
+pub fn validate_paths_parallel(
+    pool: &CpuPool,
+    paths: Vec<Vec<u8>>,
+    concurrency: usize,
+) -> BoxFuture<::std::result::Result<Vec<MPath>, Vec<(usize, Error)>>, Error> {
+    let concurrency = cmp::max(concurrency, 1);
+    // Split the paths into at most `concurrency` contiguous chunks, remembering the index each
+    // chunk starts at.
+    let chunk_size = cmp::max((paths.len() + concurrency - 1) / concurrency, 1);
+
+    let mut paths = paths.into_iter().peekable();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while paths.peek().is_some() {
+        let chunk: Vec<_> = paths.by_ref().take(chunk_size).collect();
+        let chunk_start = start;
+        start += chunk.len();
+        chunks.push(pool.spawn_fn(move || {
+            let validated: Vec<_> = chunk
+                .into_iter()
+                .enumerate()
+                .map(|(idx, path)| (chunk_start + idx, MPath::new(path)))
+                .collect();
+            Ok::<_, Error>(validated)
+        }));
+    }
+
+    join_all(chunks)
+        .and_then(move |validated_chunks| {
+            let mut valid = Vec::new();
+            let mut errors = Vec::new();
+            for (idx, res) in validated_chunks.into_iter().flat_map(|chunk| chunk) {
+                match res {
+                    Ok(path) => valid.push(path),
+                    Err(err) => errors.push((idx, err)),
+                }
+            }
+            if !errors.is_empty() {
+                return Ok(Err(errors));
+            }
+
+            valid.sort_unstable();
+            check_pcf(&valid)?;
+            Ok(Ok(valid))
+        })
+        .boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_many() {
+        let pool = CpuPool::new(4);
+        let mut paths: Vec<Vec<u8>> = (0..1000)
+            .map(|i| format!("dir{}/file{}", i % 7, i).into_bytes())
+            .collect();
+        let valid = validate_paths_parallel(&pool, paths.clone(), 4)
+            .wait()
+            .expect("unexpected Err - paths are path-conflict-free")
+            .expect("unexpected invalid paths");
+        assert_eq!(valid.len(), paths.len());
+        let mut sorted = valid.clone();
+        sorted.sort();
+        assert_eq!(valid, sorted);
+
+        paths[3] = b"".to_vec();
+        paths[500] = b"foo\0bar".to_vec();
+        paths[999] = b"foo\nbar".to_vec();
+        let errors = validate_paths_parallel(&pool, paths, 4)
+            .wait()
+            .expect("unexpected Err - per-path errors are reported separately")
+            .expect_err("unexpected OK - some paths are invalid");
+        let indexes: Vec<_> = errors.iter().map(|&(idx, _)| idx).collect();
+        assert_eq!(indexes, vec![3, 500, 999]);
+    }
+
+    #[test]
+    fn validate_conflicts() {
+        let pool = CpuPool::new(2);
+        let paths = vec![b"foo/bar".to_vec(), b"foo".to_vec()];
+        validate_paths_parallel(&pool, paths, 2)
+            .wait()
+            .expect_err("unexpected OK - foo is a prefix of foo/bar");
+
+        let valid = validate_paths_parallel(&pool, vec![], 2)
+            .wait()
+            .expect("unexpected Err - no paths")
+            .expect("unexpected invalid paths");
+        assert!(valid.is_empty());
+    }
+}
@@ -14,7 +14,7 @@ use rust_thrift::compact_protocol;
 use blob::{Blob, BlobstoreValue, ChangesetBlob};
 use datetime::DateTime;
 use errors::*;
-use file_change::FileChange;
+use file_change::{FileChange, FileChangeEntry};
 use path::{self, MPath};
 use thrift;
 use typed_hash::{ChangesetId, ChangesetIdContext};
@@ -32,6 +32,7 @@ pub struct BonsaiChangesetMut {
     pub committer_date: Option<DateTime>,
     pub message: String,
     pub extra: BTreeMap<String, String>,
+    /// The files changed, with `None` for files that were deleted.
     pub file_changes: BTreeMap<MPath, Option<FileChange>>,
 }
 
@@ -48,6 +49,16 @@ impl BonsaiChangesetMut {
     /// that's external to this changeset. For example, a changeset that deletes a file that
     /// doesn't exist in its parent is invalid. Instead, it only checks for internal consistency.
     pub fn verify(&self) -> Result<()> {
+        // Check that no parent is listed twice.
+        for (idx, parent) in self.parents.iter().enumerate() {
+            if self.parents[..idx].contains(parent) {
+                bail_err!(ErrorKind::InvalidBonsaiChangeset(format!(
+                    "parent {} is listed more than once",
+                    parent
+                )));
+            }
+        }
+
         // Check that the copy info ID refers to a parent in the parent set.
         for (path, fc_opt) in &self.file_changes {
             if let &Some(ref fc) = fc_opt {
@@ -116,9 +127,20 @@ impl BonsaiChangeset {
         self.inner.parents.iter()
     }
 
+    /// Whether this changeset has no parents.
+    pub fn is_root(&self) -> bool {
+        self.inner.parents.is_empty()
+    }
+
+    /// Whether this changeset has more than one parent.
+    pub fn is_merge(&self) -> bool {
+        self.inner.parents.len() > 1
+    }
+
     /// Get the files changed in this changeset. The items returned are guaranteed
     /// to be in depth-first traversal order: once all the changes to a particular
-    /// tree have been applied, it will never be referred to again.
+    /// tree have been applied, it will never be referred to again. This is the same
+    /// as sorted `MPath` order, regardless of the order the changes were added in.
     pub fn file_changes(&self) -> impl Iterator<Item = (&MPath, Option<&FileChange>)> {
         self.inner
             .file_changes
@@ -126,6 +148,12 @@ impl BonsaiChangeset {
             .map(|(path, fc_opt)| (path, fc_opt.as_ref()))
     }
 
+    /// Get the files changed in this changeset, in the same order as `file_changes`, with
+    /// deletions marked explicitly.
+    pub fn file_change_entries(&self) -> impl Iterator<Item = (&MPath, FileChangeEntry)> {
+        self.file_changes().map(|(path, fc_opt)| (path, FileChangeEntry::from(fc_opt)))
+    }
+
     /// Get the author for this changeset.
     pub fn author(&self) -> &str {
         &self.inner.author
@@ -165,6 +193,30 @@ impl BonsaiChangeset {
             .map(|(k, v)| (k.as_str(), v.as_str()))
     }
 
+    /// Get the extra fields for this message as a map, for looking fields up by key.
+    pub fn extra_map(&self) -> &BTreeMap<String, String> {
+        &self.inner.extra
+    }
+
+    /// Compute the ID of this changeset, the hash of its serialized form. This is the same as
+    /// the ID of its blob.
+    pub fn compute_id(&self) -> ChangesetId {
+        *self.clone().into_blob().id()
+    }
+
+    /// Check that this changeset's ID is `expected`. Fails with `ErrorKind::HashMismatch` if it
+    /// isn't.
+    pub fn verify_id(&self, expected: ChangesetId) -> Result<()> {
+        let actual = self.compute_id();
+        if actual != expected {
+            bail_err!(ErrorKind::HashMismatch {
+                expected: *expected.blake2(),
+                actual: *actual.blake2(),
+            });
+        }
+        Ok(())
+    }
+
     /// Allow mutating this instance of `BonsaiChangeset`.
     pub fn into_mut(self) -> BonsaiChangesetMut {
         self.inner
@@ -304,6 +356,167 @@ mod test {
         }
     }
 
+    fn changeset_mut(
+        parents: Vec<ChangesetId>,
+        file_changes: BTreeMap<MPath, Option<FileChange>>,
+    ) -> BonsaiChangesetMut {
+        BonsaiChangesetMut {
+            parents,
+            author: "foo".into(),
+            author_date: DateTime::from_timestamp(1234567890, 0).unwrap(),
+            committer: None,
+            committer_date: None,
+            message: "Commit message".into(),
+            extra: BTreeMap::new(),
+            file_changes,
+        }
+    }
+
+    #[test]
+    fn root_and_merge() {
+        let p1 = ChangesetId::from_byte_array([1; 32]);
+        let p2 = ChangesetId::from_byte_array([2; 32]);
+
+        let root = changeset_mut(vec![], BTreeMap::new()).freeze().unwrap();
+        assert!(root.is_root());
+        assert!(!root.is_merge());
+
+        let child = changeset_mut(vec![p1], BTreeMap::new()).freeze().unwrap();
+        assert!(!child.is_root());
+        assert!(!child.is_merge());
+        assert_eq!(child.parents().collect::<Vec<_>>(), vec![&p1]);
+
+        let merge = changeset_mut(vec![p1, p2], BTreeMap::new()).freeze().unwrap();
+        assert!(!merge.is_root());
+        assert!(merge.is_merge());
+        assert_eq!(merge.parents().collect::<Vec<_>>(), vec![&p1, &p2]);
+    }
+
+    #[test]
+    fn compute_id() {
+        let p1 = ChangesetId::from_byte_array([1; 32]);
+        let fc = |n| {
+            FileChange::new(ContentId::from_byte_array([n; 32]), FileType::Regular, 3, None)
+        };
+        let build = |paths: &[&str], extra: &[(&str, &str)]| {
+            let mut cs = changeset_mut(vec![p1], BTreeMap::new());
+            for (idx, path) in paths.iter().enumerate() {
+                cs.file_changes.insert(MPath::new(path).unwrap(), Some(fc(idx as u8)));
+            }
+            for &(key, value) in extra {
+                cs.extra.insert(key.into(), value.into());
+            }
+            cs.freeze().expect("changeset should be valid")
+        };
+
+        let cs = build(&["a", "b"], &[("k1", "v1"), ("k2", "v2")]);
+        let id = cs.compute_id();
+        assert_eq!(id, *cs.clone().into_blob().id());
+        assert_eq!(build(&["a", "b"], &[("k1", "v1"), ("k2", "v2")]).compute_id(), id);
+        // The order in which extras are added doesn't matter.
+        assert_eq!(build(&["a", "b"], &[("k2", "v2"), ("k1", "v1")]).compute_id(), id);
+        // But which file gets which contents does.
+        assert_ne!(build(&["b", "a"], &[("k1", "v1"), ("k2", "v2")]).compute_id(), id);
+
+        cs.verify_id(id).expect("ID should match");
+        let wrong_id = ChangesetId::from_byte_array([9; 32]);
+        let err = cs.verify_id(wrong_id).expect_err("unexpected OK - wrong ID");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::HashMismatch { expected, actual })
+                if expected == *wrong_id.blake2() && actual == *id.blake2() => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn file_changes_order() {
+        let mut cs = changeset_mut(vec![], BTreeMap::new());
+        for path in &["d/e", "a", "b/c", "f", "b/a/z", "c"] {
+            cs.file_changes.insert(MPath::new(path).unwrap(), None);
+        }
+        let cs = cs.freeze().expect("changeset should be valid");
+        let paths: Vec<_> = cs.file_changes()
+            .map(|(path, _)| path.to_string())
+            .collect();
+        assert_eq!(paths, vec!["a", "b/a/z", "b/c", "c", "d/e", "f"]);
+    }
+
+    #[test]
+    fn message_and_extra() {
+        let mut cs = changeset_mut(vec![], BTreeMap::new());
+        cs.message = "caf\u{e9}\n\nsummary".into();
+        cs.extra.insert("branch".into(), "stable".into());
+        cs.extra.insert("close".into(), "1".into());
+        let cs = cs.freeze().expect("changeset should be valid");
+
+        let cs2 = BonsaiChangeset::from_blob(cs.clone().into_blob())
+            .expect("blob roundtrips should always be valid");
+        assert_eq!(cs2.message(), "caf\u{e9}\n\nsummary");
+        assert_eq!(cs2.extra_map(), cs.extra_map());
+        assert_eq!(cs2.extra_map().get("branch").map(String::as_str), Some("stable"));
+        assert_eq!(
+            cs2.extra().collect::<Vec<_>>(),
+            vec![("branch", "stable"), ("close", "1")]
+        );
+    }
+
+    #[test]
+    fn duplicate_parents() {
+        let p1 = ChangesetId::from_byte_array([1; 32]);
+        let p2 = ChangesetId::from_byte_array([2; 32]);
+        changeset_mut(vec![p1, p2], BTreeMap::new())
+            .freeze()
+            .expect("distinct parents should be valid");
+        changeset_mut(vec![p1, p2, p1], BTreeMap::new())
+            .freeze()
+            .expect_err("unexpected OK - duplicate parent");
+    }
+
+    #[test]
+    fn path_conflicts() {
+        let fc = FileChange::new(ContentId::from_byte_array([1; 32]), FileType::Regular, 3, None);
+        changeset_mut(
+            vec![],
+            btreemap![
+                MPath::new("a/b").unwrap() => Some(fc.clone()),
+                MPath::new("a/c").unwrap() => Some(fc.clone()),
+            ],
+        ).freeze()
+            .expect("sibling files should be valid");
+        changeset_mut(
+            vec![],
+            btreemap![
+                MPath::new("a").unwrap() => Some(fc.clone()),
+                MPath::new("a/b").unwrap() => Some(fc),
+            ],
+        ).freeze()
+            .expect_err("unexpected OK - file is a prefix of another file");
+    }
+
+    #[test]
+    fn file_change_entries() {
+        let fc = FileChange::new(ContentId::from_byte_array([1; 32]), FileType::Regular, 42, None);
+        let cs = changeset_mut(
+            vec![ChangesetId::from_byte_array([1; 32])],
+            btreemap![
+                MPath::new("modified").unwrap() => Some(fc.clone()),
+                MPath::new("deleted").unwrap() => None,
+            ],
+        ).freeze()
+            .expect("changeset should be valid");
+
+        let entries: Vec<_> = cs.file_change_entries()
+            .map(|(path, entry)| (path.to_string(), entry.is_deletion(), entry.change().cloned()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("deleted".to_string(), true, None),
+                ("modified".to_string(), false, Some(fc)),
+            ]
+        );
+    }
+
     #[test]
     fn fixed_blob() {
         let tc = BonsaiChangesetMut {
@@ -335,6 +548,7 @@ mod test {
             ],
         };
         let tc = tc.freeze().expect("fixed bonsai changeset must be valid");
+
         let blob = tc.into_blob();
 
         assert_eq!(
@@ -4,15 +4,24 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::cmp::{self, Ordering};
 use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
 
-use chrono::{DateTime as ChronoDateTime, FixedOffset, LocalResult, TimeZone};
+use chrono::{DateTime as ChronoDateTime, Duration, FixedOffset, Local, LocalResult, TimeZone};
+use chrono::naive::{MAX_DATE, MIN_DATE};
 use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use errors::*;
 use thrift;
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+/// A point in time, together with the timezone it was recorded in.
+///
+/// DateTimes are ordered by instant first, with the timezone offset only breaking ties between
+/// the same instant in different timezones. Equality is consistent with that, so such
+/// DateTimes are *not* equal: `12:00+00:00` sorts just before `13:00+01:00`, but they are
+/// distinct values. To compare instants alone, compare `timestamp_secs`.
+#[derive(Clone, Copy, Debug)]
 pub struct DateTime(ChronoDateTime<FixedOffset>);
 
 impl DateTime {
@@ -21,10 +30,17 @@ impl DateTime {
         DateTime(dt)
     }
 
+    /// The current time, in the local timezone.
+    pub fn now() -> Self {
+        let now = Local::now();
+        DateTime::new(now.with_timezone(now.offset()))
+    }
+
+    /// Create a DateTime from a Unix timestamp in seconds, and a timezone offset as the number of
+    /// seconds to add to convert local time to UTC (see `tz_offset_secs`). The offset must be
+    /// less than a day in either direction.
     pub fn from_timestamp(secs: i64, tz_offset_secs: i32) -> Result<Self> {
-        let tz = FixedOffset::west_opt(tz_offset_secs).ok_or_else(|| {
-            ErrorKind::InvalidDateTime(format!("timezone offset out of range: {}", tz_offset_secs))
-        })?;
+        let tz = tz_from_offset(tz_offset_secs)?;
         let dt = match tz.timestamp_opt(secs, 0) {
             LocalResult::Single(dt) => dt,
             _ => bail_err!(ErrorKind::InvalidDateTime(format!(
@@ -35,6 +51,61 @@ impl DateTime {
         Ok(Self::new(dt))
     }
 
+    /// Parse a date in Mercurial's `"<unixtime> <offset>"` format. Like `tz_offset_secs`, the
+    /// offset is the number of seconds to add to local time to get UTC, so a positive offset
+    /// means a timezone behind UTC.
+    pub fn from_hg(s: &str) -> Result<Self> {
+        let invalid = || ErrorKind::InvalidDateTime(format!("invalid Mercurial date: '{}'", s));
+        let mut parts = s.split(' ');
+        let (secs, tz_offset_secs) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(secs), Some(tz_offset_secs), None) => (secs, tz_offset_secs),
+            _ => bail_err!(invalid()),
+        };
+        let secs = secs.parse().map_err(|_| invalid())?;
+        let tz_offset_secs = tz_offset_secs.parse().map_err(|_| invalid())?;
+        Self::from_timestamp(secs, tz_offset_secs)
+    }
+
+    /// Format this date in Mercurial's `"<unixtime> <offset>"` format. See `from_hg`.
+    pub fn to_hg(&self) -> String {
+        format!("{} {}", self.timestamp_secs(), self.tz_offset_secs())
+    }
+
+    /// Parse an RFC 3339 date, such as `2018-01-01T12:00:00+05:30`. The timezone offset is
+    /// kept as given. Fractional seconds are discarded, since DateTime has a resolution of one
+    /// second.
+    pub fn from_rfc3339(s: &str) -> Result<Self> {
+        let dt = ChronoDateTime::parse_from_rfc3339(s).with_context(|_| {
+            ErrorKind::InvalidDateTime(format!("invalid RFC 3339 date: '{}'", s))
+        })?;
+        Self::from_timestamp(dt.timestamp(), dt.offset().utc_minus_local())
+    }
+
+    /// Format this date as RFC 3339, with its timezone offset.
+    pub fn to_rfc3339(&self) -> String {
+        self.0.to_rfc3339()
+    }
+
+    /// Add `d` to this time, keeping its timezone. Rather than overflowing, the result is
+    /// clamped to the earliest or latest time whose UTC and local times are both representable.
+    pub fn saturating_add(&self, d: Duration) -> DateTime {
+        let offset = Duration::seconds(i64::from(self.0.offset().local_minus_utc()));
+        let max = MAX_DATE.and_hms(23, 59, 59) - cmp::max(offset, Duration::zero());
+        let min = MIN_DATE.and_hms(0, 0, 0) - cmp::min(offset, Duration::zero());
+        let utc = match self.0.naive_utc().checked_add_signed(d) {
+            Some(utc) => cmp::max(min, cmp::min(max, utc)),
+            None if d > Duration::zero() => max,
+            None => min,
+        };
+        DateTime::new(self.0.offset().from_utc_datetime(&utc))
+    }
+
+    /// The time elapsed from `earlier` to this time, regardless of their timezones. This is
+    /// negative if `earlier` is in fact later.
+    pub fn duration_since(&self, earlier: &DateTime) -> Duration {
+        self.0.signed_duration_since(earlier.0)
+    }
+
     pub(crate) fn from_thrift(dt: thrift::DateTime) -> Result<Self> {
         Self::from_timestamp(dt.timestamp_secs, dt.tz_offset_secs)
     }
@@ -53,6 +124,18 @@ impl DateTime {
         self.0.offset().utc_minus_local()
     }
 
+    /// The same instant in UTC.
+    pub fn as_utc(&self) -> DateTime {
+        DateTime::new(self.0.with_timezone(&FixedOffset::east(0)))
+    }
+
+    /// The same instant in the timezone with the given offset, in the same convention as
+    /// `tz_offset_secs`. The offset must be less than a day in either direction.
+    pub fn with_tz_offset(&self, tz_offset_secs: i32) -> Result<DateTime> {
+        let tz = tz_from_offset(tz_offset_secs)?;
+        Ok(DateTime::new(self.0.with_timezone(&tz)))
+    }
+
     #[inline]
     pub fn as_chrono(&self) -> &ChronoDateTime<FixedOffset> {
         &self.0
@@ -71,6 +154,89 @@ impl DateTime {
     }
 }
 
+fn tz_from_offset(tz_offset_secs: i32) -> Result<FixedOffset> {
+    let tz = FixedOffset::west_opt(tz_offset_secs).ok_or_else(|| {
+        ErrorKind::InvalidDateTime(format!("timezone offset out of range: {}", tz_offset_secs))
+    })?;
+    Ok(tz)
+}
+
+/// A range of times, for example to select the changesets committed in a time window. A bound
+/// of `None` leaves that end of the range open. The start is inclusive and the end is
+/// exclusive.
+///
+/// Times are compared as instants, regardless of their timezones.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DateTimeRange {
+    start: Option<DateTime>,
+    end: Option<DateTime>,
+}
+
+impl DateTimeRange {
+    pub fn new(start: Option<DateTime>, end: Option<DateTime>) -> Result<Self> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start.timestamp_secs() > end.timestamp_secs() {
+                bail_err!(ErrorKind::InvalidDateTime(format!(
+                    "range start {} is after range end {}",
+                    start, end
+                )));
+            }
+        }
+        Ok(DateTimeRange { start, end })
+    }
+
+    #[inline]
+    pub fn start(&self) -> Option<&DateTime> {
+        self.start.as_ref()
+    }
+
+    #[inline]
+    pub fn end(&self) -> Option<&DateTime> {
+        self.end.as_ref()
+    }
+
+    pub fn contains(&self, dt: &DateTime) -> bool {
+        let secs = dt.timestamp_secs();
+        self.start
+            .map_or(true, |start| start.timestamp_secs() <= secs)
+            && self.end.map_or(true, |end| secs < end.timestamp_secs())
+    }
+}
+
+impl DateTime {
+    fn sort_key(&self) -> (ChronoDateTime<FixedOffset>, i32) {
+        (self.0, self.tz_offset_secs())
+    }
+}
+
+impl PartialEq for DateTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for DateTime {}
+
+impl PartialOrd for DateTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DateTime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Chrono compares DateTimes by instant, ignoring their offsets.
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl Hash for DateTime {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.timestamp_secs().hash(state);
+        self.tz_offset_secs().hash(state);
+    }
+}
+
 impl Display for DateTime {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(fmt, "{}", self.0)
@@ -98,14 +264,184 @@ mod test {
     use super::*;
 
     quickcheck! {
+        fn hg_roundtrip(dt: DateTime) -> bool {
+            let dt2 = DateTime::from_hg(&dt.to_hg()).expect("roundtrip should always be valid");
+            dt == dt2 && dt.tz_offset_secs() == dt2.tz_offset_secs()
+        }
+
+        fn rfc3339_roundtrip(dt: DateTime) -> bool {
+            let dt2 = DateTime::from_rfc3339(&dt.to_rfc3339())
+                .expect("roundtrip should always be valid");
+            dt == dt2 && dt.tz_offset_secs() == dt2.tz_offset_secs()
+        }
+
         fn thrift_roundtrip(dt: DateTime) -> bool {
             let thrift_dt = dt.into_thrift();
             let dt2 = DateTime::from_thrift(thrift_dt)
                 .expect("roundtrip instances should always be valid");
-            // Equality on DateTime structs doesn't pay attention to the time zone,
-            // in order to be consistent with Ord.
             dt == dt2 && dt.tz_offset_secs() == dt2.tz_offset_secs()
         }
+
+        fn utc_roundtrip(dt: DateTime) -> bool {
+            let utc = dt.as_utc();
+            utc.timestamp_secs() == dt.timestamp_secs() && utc.tz_offset_secs() == 0
+                && utc.with_tz_offset(dt.tz_offset_secs()).unwrap() == dt
+        }
+
+        fn ord_by_instant(dt1: DateTime, dt2: DateTime) -> bool {
+            match dt1.timestamp_secs().cmp(&dt2.timestamp_secs()) {
+                Ordering::Equal => {
+                    dt1.cmp(&dt2) == dt1.tz_offset_secs().cmp(&dt2.tz_offset_secs())
+                }
+                ordering => dt1.cmp(&dt2) == ordering,
+            }
+        }
+    }
+
+    #[test]
+    fn tz_offset_conversion() {
+        let dt = DateTime::from_rfc3339("2018-01-01T12:00:00+05:30").unwrap();
+        assert_eq!(dt.as_utc().to_rfc3339(), "2018-01-01T06:30:00+00:00");
+        assert_eq!(
+            dt.with_tz_offset(3600).unwrap().to_rfc3339(),
+            "2018-01-01T05:30:00-01:00"
+        );
+        assert_eq!(dt.as_utc().with_tz_offset(dt.tz_offset_secs()).unwrap(), dt);
+
+        dt.with_tz_offset(86_400)
+            .expect_err("unexpected OK - tz_offset_secs out of bounds");
+        dt.with_tz_offset(-86_400)
+            .expect_err("unexpected OK - tz_offset_secs out of bounds");
+    }
+
+    #[test]
+    fn saturating_add() {
+        let dt = DateTime::from_timestamp(1_000, 3600).unwrap();
+        let later = dt.saturating_add(Duration::seconds(500));
+        assert_eq!(later.timestamp_secs(), 1_500);
+        assert_eq!(later.tz_offset_secs(), 3600);
+        assert_eq!(
+            dt.saturating_add(Duration::seconds(-2_000)).timestamp_secs(),
+            -1_000
+        );
+    }
+
+    #[test]
+    fn saturating_add_clamps() {
+        let max_dt = MAX_DATE.and_hms(23, 59, 59);
+        let min_dt = MIN_DATE.and_hms(0, 0, 0);
+        // Offsets behind and ahead of UTC: the local time is past the UTC time's bound in one
+        // direction or the other.
+        for &tz_offset_secs in &[3600, -3600] {
+            let dt = DateTime::from_timestamp(1_000, tz_offset_secs).unwrap();
+
+            let max = dt.saturating_add(Duration::max_value());
+            assert!(max > dt);
+            assert_eq!(max.tz_offset_secs(), tz_offset_secs);
+            assert_eq!(max.saturating_add(Duration::seconds(1)), max);
+            let chrono = max.as_chrono();
+            assert_eq!(cmp::max(chrono.naive_utc(), chrono.naive_local()), max_dt);
+
+            let min = dt.saturating_add(Duration::min_value());
+            assert!(min < dt);
+            assert_eq!(min.tz_offset_secs(), tz_offset_secs);
+            assert_eq!(min.saturating_add(Duration::seconds(-1)), min);
+            let chrono = min.as_chrono();
+            assert_eq!(cmp::min(chrono.naive_utc(), chrono.naive_local()), min_dt);
+
+            // Formatting needs the local time to be representable.
+            for clamped in &[max, min] {
+                assert!(!format!("{}", clamped).is_empty());
+                assert!(!format!("{:?}", clamped).is_empty());
+                assert!(!clamped.to_rfc3339().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn duration_since() {
+        let earlier = DateTime::from_timestamp(1_000, 0).unwrap();
+        let later = DateTime::from_timestamp(1_500, -7200).unwrap();
+        assert_eq!(later.duration_since(&earlier), Duration::seconds(500));
+        assert_eq!(earlier.duration_since(&later), Duration::seconds(-500));
+        assert_eq!(earlier.duration_since(&earlier), Duration::zero());
+    }
+
+    #[test]
+    fn ord_same_instant() {
+        // 12:00+00:00 and 13:00+01:00 are the same instant.
+        let utc = DateTime::from_rfc3339("2018-01-01T12:00:00+00:00").unwrap();
+        let plus_one = DateTime::from_rfc3339("2018-01-01T13:00:00+01:00").unwrap();
+        assert_eq!(utc.timestamp_secs(), plus_one.timestamp_secs());
+        assert_ne!(utc, plus_one);
+        // +01:00 is an offset of -3600 seconds, so it sorts first.
+        assert_eq!(plus_one.cmp(&utc), Ordering::Less);
+        assert_eq!(utc.cmp(&plus_one), Ordering::Greater);
+        assert_eq!(utc.cmp(&utc), Ordering::Equal);
+
+        // A later instant sorts later, whatever the offsets.
+        let later = DateTime::from_rfc3339("2018-01-01T12:30:00+01:00").unwrap();
+        assert!(later < DateTime::from_rfc3339("2018-01-01T12:00:00-01:00").unwrap());
+        assert!(utc < DateTime::from_rfc3339("2018-01-01T17:00:01+05:00").unwrap());
+    }
+
+    #[test]
+    fn timestamp_roundtrip() {
+        let dt = DateTime::from_timestamp(1_500_000_000, -19_800).unwrap();
+        assert_eq!(dt.timestamp_secs(), 1_500_000_000);
+        assert_eq!(dt.tz_offset_secs(), -19_800);
+        assert_eq!(dt.as_chrono().offset().local_minus_utc(), 19_800);
+    }
+
+    #[test]
+    fn now() {
+        let before = ::std::time::SystemTime::now()
+            .duration_since(::std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let now = DateTime::now();
+        assert!(now.timestamp_secs() >= before);
+        assert!(now.tz_offset_secs().abs() < 86_400);
+        DateTime::from_timestamp(now.timestamp_secs(), now.tz_offset_secs())
+            .expect("now should always be valid");
+    }
+
+    #[test]
+    fn rfc3339() {
+        let dt = DateTime::from_rfc3339("2018-01-01T12:00:00+05:30").unwrap();
+        assert_eq!(dt.timestamp_secs(), 1_514_788_200);
+        assert_eq!(dt.tz_offset_secs(), -19_800);
+        assert_eq!(dt.to_rfc3339(), "2018-01-01T12:00:00+05:30");
+
+        let dt = DateTime::from_rfc3339("2018-01-01T12:00:00.75Z").unwrap();
+        assert_eq!(dt.to_rfc3339(), "2018-01-01T12:00:00+00:00");
+
+        DateTime::from_rfc3339("2018-01-01 12:00:00").expect_err("unexpected OK - no offset");
+        DateTime::from_rfc3339("not a date").expect_err("unexpected OK - not a date");
+    }
+
+    #[test]
+    fn hg_format() {
+        let dt = DateTime::from_hg("1514764800 -3600").unwrap();
+        assert_eq!(dt.timestamp_secs(), 1_514_764_800);
+        // A negative offset is ahead of UTC.
+        assert_eq!(dt.as_chrono().offset().local_minus_utc(), 3600);
+        assert_eq!(dt.to_hg(), "1514764800 -3600");
+
+        for bad in &[
+            "",
+            "1514764800",
+            "1514764800 ",
+            " 1514764800 0",
+            "1514764800 0 0",
+            "1514764800  0",
+            "abc 0",
+            "1514764800 abc",
+            "1514764800.5 0",
+            "1514764800 86400",
+        ] {
+            DateTime::from_hg(bad).expect_err(&format!("unexpected OK - parsed '{}'", bad));
+        }
     }
 
     #[test]
@@ -120,6 +456,54 @@ mod test {
             .expect_err("unexpected OK - timestamp_secs out of bounds");
     }
 
+    #[test]
+    fn range_open() {
+        let dt = |secs| DateTime::from_timestamp(secs, 0).unwrap();
+
+        let unbounded = DateTimeRange::new(None, None).unwrap();
+        assert!(unbounded.contains(&dt(i32::min_value() as i64)));
+        assert!(unbounded.contains(&dt(i32::max_value() as i64)));
+
+        let from = DateTimeRange::new(Some(dt(100)), None).unwrap();
+        assert!(!from.contains(&dt(99)));
+        assert!(from.contains(&dt(100)));
+        assert!(from.contains(&dt(i32::max_value() as i64)));
+
+        let until = DateTimeRange::new(None, Some(dt(100))).unwrap();
+        assert!(until.contains(&dt(i32::min_value() as i64)));
+        assert!(until.contains(&dt(99)));
+        assert!(!until.contains(&dt(100)));
+    }
+
+    #[test]
+    fn range_closed() {
+        let range = DateTimeRange::new(
+            Some(DateTime::from_timestamp(100, 0).unwrap()),
+            Some(DateTime::from_timestamp(200, 0).unwrap()),
+        ).unwrap();
+        assert!(!range.contains(&DateTime::from_timestamp(99, 0).unwrap()));
+        assert!(range.contains(&DateTime::from_timestamp(100, 0).unwrap()));
+        assert!(range.contains(&DateTime::from_timestamp(199, 0).unwrap()));
+        assert!(!range.contains(&DateTime::from_timestamp(200, 0).unwrap()));
+        // Bounds are compared by instant, so the timezone doesn't matter.
+        assert!(range.contains(&DateTime::from_timestamp(150, 7200).unwrap()));
+        assert!(!range.contains(&DateTime::from_timestamp(250, -7200).unwrap()));
+        assert!(range.contains(&DateTime::from_timestamp(100, 7200).unwrap()));
+        assert!(range.contains(&DateTime::from_timestamp(100, -7200).unwrap()));
+        assert!(!range.contains(&DateTime::from_timestamp(200, -7200).unwrap()));
+
+        let empty = DateTimeRange::new(range.start().cloned(), range.start().cloned()).unwrap();
+        assert!(!empty.contains(&DateTime::from_timestamp(100, 0).unwrap()));
+    }
+
+    #[test]
+    fn range_inverted() {
+        DateTimeRange::new(
+            Some(DateTime::from_timestamp(200, 0).unwrap()),
+            Some(DateTime::from_timestamp(100, 0).unwrap()),
+        ).expect_err("unexpected OK - start is after end");
+    }
+
     #[test]
     fn bad_thrift() {
         DateTime::from_thrift(thrift::DateTime {
@@ -7,6 +7,8 @@
 pub use failure::{Error, ResultExt};
 
 use MPath;
+use file_change::FileType;
+use hash::Blake2;
 
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
@@ -18,7 +20,20 @@ pub enum ErrorKind {
     #[fail(display = "invalid changeset date: {}", _0)] InvalidDateTime(String),
     #[fail(display = "not path-prefix-free: path '{}' is a prefix of '{}'", _0, _1)]
     NotPathPrefixFree(MPath, MPath),
+    #[fail(display = "case conflict: paths '{}' and '{}' differ only by case", _0, _1)]
+    CaseConflict(MPath, MPath),
     #[fail(display = "invalid bonsai changeset: {}", _0)] InvalidBonsaiChangeset(String),
+    #[fail(display = "file type cannot change from {:?} to {:?}", _0, _1)]
+    InvalidFileTypeTransition(FileType, FileType),
+    #[fail(display = "invalid tagged content id: {}", _0)] InvalidTaggedContentId(String),
+    #[fail(display = "contents are invalid for file type {:?}: {}", _0, _1)]
+    InvalidFileContents(FileType, String),
+    #[fail(display = "invalid blobstore key '{}' for {}", _0, _1)]
+    InvalidBlobstoreKey(String, &'static str),
+    #[fail(display = "path '{}' is recorded as copied from itself", _0)] SelfCopy(MPath),
+    #[fail(display = "unsupported file mode {:o}", _0)] InvalidFileMode(u32),
+    #[fail(display = "hash mismatch: expected {}, got {}", expected, actual)]
+    HashMismatch { expected: Blake2, actual: Blake2 },
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -6,11 +6,15 @@
 
 use quickcheck::{empty_shrinker, single_shrinker, Arbitrary, Gen};
 
+use blob::BlobstoreValue;
 use errors::*;
+use file_contents::FileContents;
 use path::MPath;
 use thrift;
 use typed_hash::{ChangesetId, ContentId};
 
+/// New contents for a file in a changeset. A changeset records deleting a file as a `None` in
+/// place of a `FileChange`, so a `FileChange` is never a deletion; see `FileChangeEntry`.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct FileChange {
     content_id: ContentId,
@@ -35,6 +39,35 @@ impl FileChange {
         }
     }
 
+    /// Create a change to `path` that records it as copied from `copy_from`. Fails with
+    /// `ErrorKind::SelfCopy` if `copy_from` names `path` itself.
+    pub fn new_with_copy(
+        content_id: ContentId,
+        file_type: FileType,
+        size: u64,
+        path: &MPath,
+        copy_from: (MPath, ChangesetId),
+    ) -> Result<Self> {
+        if copy_from.0 == *path {
+            bail_err!(ErrorKind::SelfCopy(path.clone()));
+        }
+        Ok(Self::new(content_id, file_type, size, Some(copy_from)))
+    }
+
+    /// Create a change for the given contents, computing its content ID and size. This fails if
+    /// the contents aren't valid for `file_type`; see `verify_contents`.
+    pub fn from_contents(
+        contents: &FileContents,
+        file_type: FileType,
+        copy_from: Option<(MPath, ChangesetId)>,
+    ) -> Result<Self> {
+        let size = contents.size() as u64;
+        let content_id = *contents.clone().into_blob().id();
+        let fc = Self::new(content_id, file_type, size, copy_from);
+        fc.verify_contents(contents)?;
+        Ok(fc)
+    }
+
     pub(crate) fn from_thrift_opt(
         fc_opt: thrift::FileChangeOpt,
         mpath: &MPath,
@@ -85,6 +118,36 @@ impl FileChange {
         self.copy_from.as_ref()
     }
 
+    /// Check that this change is a valid replacement for a file that previously had the
+    /// given file type. See `FileType::can_transition_to` for the rules.
+    pub fn verify_transition_from(&self, old_file_type: FileType) -> Result<()> {
+        if !old_file_type.can_transition_to(self.file_type) {
+            bail_err!(ErrorKind::InvalidFileTypeTransition(
+                old_file_type,
+                self.file_type
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that `contents` are consistent with this change: the size must match, and a symlink
+    /// must have a valid target (see `FileContents::as_symlink_target`). Regular and executable
+    /// files can have any contents.
+    pub fn verify_contents(&self, contents: &FileContents) -> Result<()> {
+        let invalid = |msg: String| ErrorKind::InvalidFileContents(self.file_type, msg);
+        if contents.size() as u64 != self.size {
+            bail_err!(invalid(format!(
+                "size is {}, but the change declares {}",
+                contents.size(),
+                self.size
+            )));
+        }
+        if self.file_type == FileType::Symlink {
+            contents.as_symlink_target()?;
+        }
+        Ok(())
+    }
+
     #[inline]
     pub(crate) fn into_thrift_opt(fc_opt: Option<Self>) -> thrift::FileChangeOpt {
         let fc_opt = fc_opt.map(Self::into_thrift);
@@ -121,6 +184,39 @@ impl FileChange {
     }
 }
 
+/// What a changeset does to one file: either changes it, or deletes it. This is the same as the
+/// `Option<&FileChange>` for the file, with `None` meaning the file was deleted, but spelled out.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileChangeEntry<'a> {
+    Change(&'a FileChange),
+    Deletion,
+}
+
+impl<'a> FileChangeEntry<'a> {
+    #[inline]
+    pub fn is_deletion(&self) -> bool {
+        *self == FileChangeEntry::Deletion
+    }
+
+    /// The new contents for the file, or `None` if it was deleted.
+    #[inline]
+    pub fn change(&self) -> Option<&'a FileChange> {
+        match *self {
+            FileChangeEntry::Change(fc) => Some(fc),
+            FileChangeEntry::Deletion => None,
+        }
+    }
+}
+
+impl<'a> From<Option<&'a FileChange>> for FileChangeEntry<'a> {
+    fn from(fc_opt: Option<&'a FileChange>) -> Self {
+        match fc_opt {
+            Some(fc) => FileChangeEntry::Change(fc),
+            None => FileChangeEntry::Deletion,
+        }
+    }
+}
+
 impl Arbitrary for FileChange {
     fn arbitrary<G: Gen>(g: &mut G) -> Self {
         let copy_from = if g.gen_weighted_bool(5) {
@@ -167,6 +263,40 @@ pub enum FileType {
 }
 
 impl FileType {
+    /// Whether a file of this type can be changed into a file of type `other` in place.
+    ///
+    /// Flipping the executable bit is always allowed. Changing a file into a symlink, or a
+    /// symlink into a file, is rejected by some backends, so such transitions are flagged.
+    pub fn can_transition_to(&self, other: FileType) -> bool {
+        match (*self, other) {
+            (FileType::Symlink, FileType::Symlink) => true,
+            (FileType::Symlink, _) | (_, FileType::Symlink) => false,
+            (FileType::Regular, _) | (FileType::Executable, _) => true,
+        }
+    }
+
+    /// The file type for a POSIX mode, as found in git trees. Only the modes git uses for
+    /// regular files (0o100644), executables (0o100755) and symlinks (0o120000) are accepted;
+    /// anything else, such as a submodule's 0o160000, fails with `ErrorKind::InvalidFileMode`.
+    pub fn from_mode(mode: u32) -> Result<Self> {
+        let file_type = match mode {
+            0o100644 => FileType::Regular,
+            0o100755 => FileType::Executable,
+            0o120000 => FileType::Symlink,
+            _ => bail_err!(ErrorKind::InvalidFileMode(mode)),
+        };
+        Ok(file_type)
+    }
+
+    /// The POSIX mode for this file type, as accepted by `from_mode`.
+    pub fn to_mode(&self) -> u32 {
+        match *self {
+            FileType::Regular => 0o100644,
+            FileType::Executable => 0o100755,
+            FileType::Symlink => 0o120000,
+        }
+    }
+
     pub(crate) fn from_thrift(ft: thrift::FileType) -> Result<Self> {
         let file_type = match ft {
             thrift::FileType::Regular => FileType::Regular,
@@ -207,6 +337,8 @@ impl Arbitrary for FileType {
 mod test {
     use super::*;
 
+    use typed_hash::MononokeId;
+
     quickcheck! {
         fn filetype_thrift_roundtrip(ft: FileType) -> bool {
             let thrift_ft = ft.into_thrift();
@@ -215,6 +347,10 @@ mod test {
             ft == ft2
         }
 
+        fn filetype_mode_roundtrip(ft: FileType) -> bool {
+            FileType::from_mode(ft.to_mode()).ok() == Some(ft)
+        }
+
         fn filechange_thrift_roundtrip(fc: FileChange) -> bool {
             let thrift_fc = fc.clone().into_thrift();
             let fc2 = FileChange::from_thrift(thrift_fc, &MPath::new("foo").unwrap())
@@ -223,6 +359,107 @@ mod test {
         }
     }
 
+    #[test]
+    fn copy_from() {
+        let content_id = ContentId::from_data(b"foo");
+        let cs_id = ChangesetId::from_data(b"parent");
+        let path = MPath::new("dir/new").unwrap();
+        let old_path = MPath::new("dir/old").unwrap();
+
+        let fc = FileChange::new(content_id, FileType::Regular, 3, None);
+        assert_eq!(fc.copy_from(), None);
+
+        let fc = FileChange::new_with_copy(
+            content_id,
+            FileType::Regular,
+            3,
+            &path,
+            (old_path.clone(), cs_id),
+        ).expect("rename should be valid");
+        assert_eq!(fc.copy_from(), Some(&(old_path, cs_id)));
+
+        let err = FileChange::new_with_copy(
+            content_id,
+            FileType::Regular,
+            3,
+            &path,
+            (path.clone(), cs_id),
+        ).expect_err("unexpected OK - copied from itself");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::SelfCopy(ref p)) if *p == path => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn filetype_modes() {
+        assert_eq!(FileType::from_mode(0o100644).unwrap(), FileType::Regular);
+        assert_eq!(FileType::from_mode(0o100755).unwrap(), FileType::Executable);
+        assert_eq!(FileType::from_mode(0o120000).unwrap(), FileType::Symlink);
+        assert_eq!(FileType::Regular.to_mode(), 0o100644);
+        assert_eq!(FileType::Executable.to_mode(), 0o100755);
+        assert_eq!(FileType::Symlink.to_mode(), 0o120000);
+
+        let err = FileType::from_mode(0o160000).expect_err("unexpected OK - gitlink");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::InvalidFileMode(0o160000)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        FileType::from_mode(0o040000).expect_err("unexpected OK - directory");
+        FileType::from_mode(0o100600).expect_err("unexpected OK - unusual permissions");
+    }
+
+    #[test]
+    fn filetype_transitions() {
+        assert!(FileType::Regular.can_transition_to(FileType::Executable));
+        assert!(FileType::Executable.can_transition_to(FileType::Regular));
+        assert!(FileType::Symlink.can_transition_to(FileType::Symlink));
+        assert!(!FileType::Regular.can_transition_to(FileType::Symlink));
+        assert!(!FileType::Symlink.can_transition_to(FileType::Executable));
+
+        let fc = FileChange::new(
+            ContentId::from_byte_array([1; 32]),
+            FileType::Executable,
+            42,
+            None,
+        );
+        fc.verify_transition_from(FileType::Regular)
+            .expect("unexpected Err - mode flip is allowed");
+
+        let fc = FileChange::new(
+            ContentId::from_byte_array([1; 32]),
+            FileType::Symlink,
+            42,
+            None,
+        );
+        fc.verify_transition_from(FileType::Regular)
+            .expect_err("unexpected OK - regular file to symlink is flagged");
+    }
+
+    #[test]
+    fn verify_contents() {
+        let target = FileContents::new_bytes("../foo/bar");
+        let fc = FileChange::from_contents(&target, FileType::Symlink, None)
+            .expect("unexpected Err - valid symlink");
+        assert_eq!(fc.size(), 10);
+        assert_eq!(fc.content_id(), target.clone().into_blob().id());
+
+        FileChange::from_contents(&FileContents::new_bytes("foo\0bar"), FileType::Symlink, None)
+            .expect_err("unexpected OK - symlink target contains a NUL byte");
+        FileChange::from_contents(&FileContents::new_bytes(""), FileType::Symlink, None)
+            .expect_err("unexpected OK - symlink target is empty");
+        for file_type in &[FileType::Regular, FileType::Executable] {
+            FileChange::from_contents(&FileContents::new_bytes(""), *file_type, None)
+                .expect("unexpected Err - files can be empty");
+            FileChange::from_contents(&FileContents::new_bytes("foo\0bar"), *file_type, None)
+                .expect("unexpected Err - files can contain any bytes");
+        }
+
+        let fc = FileChange::new(*fc.content_id(), FileType::Symlink, 42, None);
+        fc.verify_contents(&target)
+            .expect_err("unexpected OK - size doesn't match");
+    }
+
     #[test]
     fn bad_filetype_thrift() {
         let thrift_ft = thrift::FileType(42);
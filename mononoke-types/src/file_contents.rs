@@ -4,19 +4,31 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::cmp;
+use std::collections::VecDeque;
 use std::fmt::{self, Debug};
+use std::io::{self, Read};
 
 use bytes::Bytes;
 use failure::SyncFailure;
 use quickcheck::{single_shrinker, Arbitrary, Gen};
+use tokio_io::AsyncRead;
 
 use rust_thrift::compact_protocol;
 
 use blob::{Blob, BlobstoreValue, ContentBlob};
 use errors::*;
+use file_change::FileType;
 use thrift;
 use typed_hash::{ContentId, ContentIdContext};
 
+/// How many leading bytes `FileContents::is_binary` looks at.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// The longest symlink target `FileContents::as_symlink_target` accepts. This is Linux's
+/// `PATH_MAX` less the terminating NUL.
+const MAX_SYMLINK_TARGET_LEN: usize = 4095;
+
 /// An enum representing contents for a file. In the future this may have
 /// special support for very large files.
 #[derive(Clone, Eq, PartialEq)]
@@ -53,12 +65,148 @@ impl FileContents {
         }
     }
 
+    /// Whether this looks like a binary file, i.e. contains a NUL byte near the start. Like git,
+    /// only the first few kilobytes are checked.
+    pub fn is_binary(&self) -> bool {
+        match self {
+            FileContents::Bytes(b) => {
+                let check_len = cmp::min(b.len(), BINARY_CHECK_LEN);
+                b[..check_len].contains(&0)
+            }
+        }
+    }
+
+    /// Guess the text encoding of these contents. A byte order mark identifies UTF-8 or UTF-16;
+    /// without one, contents that are valid UTF-8 are assumed to be UTF-8. Returns `None` if
+    /// neither applies.
+    ///
+    /// Note that UTF-16 text usually contains NUL bytes, so `is_binary` is true for it.
+    pub fn guess_text_encoding(&self) -> Option<Encoding> {
+        let bytes = match self {
+            FileContents::Bytes(b) => b,
+        };
+        if bytes.starts_with(b"\xef\xbb\xbf") {
+            Some(Encoding::Utf8)
+        } else if bytes.starts_with(b"\xff\xfe") {
+            Some(Encoding::Utf16Le)
+        } else if bytes.starts_with(b"\xfe\xff") {
+            Some(Encoding::Utf16Be)
+        } else if ::std::str::from_utf8(bytes).is_ok() {
+            Some(Encoding::Utf8)
+        } else {
+            None
+        }
+    }
+
+    /// Interpret these contents as the target of a symlink. The target must be non-empty, at
+    /// most 4095 bytes long and free of NUL bytes; otherwise this fails with
+    /// `ErrorKind::InvalidFileContents`. Targets are returned as raw bytes rather than an
+    /// `MPath`, since they may be absolute or contain `..` components.
+    pub fn as_symlink_target(&self) -> Result<&[u8]> {
+        let target = match self {
+            FileContents::Bytes(b) => b.as_ref(),
+        };
+        let invalid = |msg: String| ErrorKind::InvalidFileContents(FileType::Symlink, msg);
+        if target.is_empty() {
+            bail_err!(invalid("symlink target is empty".into()));
+        }
+        if target.len() > MAX_SYMLINK_TARGET_LEN {
+            bail_err!(invalid(format!(
+                "symlink target is {} bytes long, but at most {} are allowed",
+                target.len(),
+                MAX_SYMLINK_TARGET_LEN
+            )));
+        }
+        if target.contains(&0) {
+            bail_err!(invalid("symlink target contains a NUL byte".into()));
+        }
+        Ok(target)
+    }
+
+    /// Count the lines in this file and the line endings they use. Binary files (see
+    /// `is_binary`) aren't scanned; their stats only have `is_binary` set.
+    pub fn line_stats(&self) -> LineStats {
+        let bytes = match self {
+            FileContents::Bytes(b) => b,
+        };
+        let mut stats = LineStats::default();
+        if self.is_binary() {
+            stats.is_binary = true;
+            return stats;
+        }
+
+        let mut iter = bytes.iter().peekable();
+        while let Some(b) = iter.next() {
+            match *b {
+                b'\n' => stats.lf += 1,
+                b'\r' => {
+                    if iter.peek() == Some(&&b'\n') {
+                        iter.next();
+                        stats.crlf += 1;
+                    } else {
+                        stats.cr += 1;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        stats.ends_with_newline = match bytes.last() {
+            Some(&b'\n') | Some(&b'\r') => true,
+            _ => false,
+        };
+        stats.lines = stats.lf + stats.crlf + stats.cr;
+        if !bytes.is_empty() && !stats.ends_with_newline {
+            // The last line has no line ending.
+            stats.lines += 1;
+        }
+        stats
+    }
+
+    /// How these contents should be stored: inline if they're at most `inline_threshold` bytes
+    /// long, and in chunks otherwise.
+    pub fn storage_class(&self, inline_threshold: u64) -> StorageClass {
+        if self.size() as u64 > inline_threshold {
+            StorageClass::Chunked
+        } else {
+            StorageClass::Inline
+        }
+    }
+
+    /// Convert these contents into the form they should be stored in (see `storage_class`).
+    /// Chunked contents are split into chunks of `chunk_size` bytes, except for the last chunk
+    /// which may be shorter. The chunks share memory with the original contents.
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn into_storage_form(self, inline_threshold: u64, chunk_size: usize) -> StoredContents {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        match self.storage_class(inline_threshold) {
+            StorageClass::Inline => StoredContents::Inline(self),
+            StorageClass::Chunked => {
+                let bytes = self.into_bytes();
+                let num_chunks = (bytes.len() + chunk_size - 1) / chunk_size;
+                let chunks = (0..num_chunks)
+                    .map(|idx| {
+                        let start = idx * chunk_size;
+                        bytes.slice(start, cmp::min(start + chunk_size, bytes.len()))
+                    })
+                    .collect();
+                StoredContents::Chunked(chunks)
+            }
+        }
+    }
+
     pub fn into_bytes(self) -> Bytes {
         match self {
             FileContents::Bytes(bytes) => bytes,
         }
     }
 
+    /// A reader over these contents, for streaming them out without copying them first.
+    pub fn into_async_read(self) -> impl AsyncRead {
+        ContentsReader::new(vec![self.into_bytes()])
+    }
+
     pub(crate) fn into_thrift(self) -> thrift::FileContents {
         match self {
             // TODO (T26959816) -- allow Thrift to represent binary as Bytes
@@ -67,6 +215,93 @@ impl FileContents {
     }
 }
 
+/// Line counts for a `FileContents`, as returned by `FileContents::line_stats`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LineStats {
+    /// The number of lines, including a final line without a line ending.
+    pub lines: usize,
+    /// The number of `\n` line endings (not counting those in `\r\n`).
+    pub lf: usize,
+    /// The number of `\r\n` line endings.
+    pub crlf: usize,
+    /// The number of lone `\r` line endings.
+    pub cr: usize,
+    /// Whether the file ends with a line ending.
+    pub ends_with_newline: bool,
+    /// Whether the file is binary, in which case the other fields are all zero.
+    pub is_binary: bool,
+}
+
+/// A text encoding, as returned by `FileContents::guess_text_encoding`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// Where file contents are stored, as returned by `FileContents::storage_class`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StorageClass {
+    /// Stored as a single blob.
+    Inline,
+    /// Split into chunks that are stored separately.
+    Chunked,
+}
+
+/// File contents in the form they're stored in, as returned by
+/// `FileContents::into_storage_form`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StoredContents {
+    Inline(FileContents),
+    Chunked(Vec<Bytes>),
+}
+
+impl StoredContents {
+    /// A reader over the file contents. Chunked contents are read one chunk at a time, without
+    /// being joined together first.
+    pub fn into_async_read(self) -> impl AsyncRead {
+        match self {
+            StoredContents::Inline(contents) => ContentsReader::new(vec![contents.into_bytes()]),
+            StoredContents::Chunked(chunks) => ContentsReader::new(chunks),
+        }
+    }
+}
+
+/// Reads a sequence of in-memory chunks back to back. Never blocks, so it's usable both as a
+/// `Read` and an `AsyncRead`.
+struct ContentsReader {
+    chunks: VecDeque<Bytes>,
+}
+
+impl ContentsReader {
+    fn new(chunks: Vec<Bytes>) -> Self {
+        ContentsReader {
+            chunks: chunks.into_iter().filter(|chunk| !chunk.is_empty()).collect(),
+        }
+    }
+}
+
+impl Read for ContentsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (len, chunk_done) = match self.chunks.front_mut() {
+            // All the chunks have been read.
+            None => return Ok(0),
+            Some(chunk) => {
+                let len = cmp::min(buf.len(), chunk.len());
+                buf[..len].copy_from_slice(&chunk.split_to(len));
+                (len, chunk.is_empty())
+            }
+        };
+        if chunk_done {
+            self.chunks.pop_front();
+        }
+        Ok(len)
+    }
+}
+
+impl AsyncRead for ContentsReader {}
+
 impl BlobstoreValue for FileContents {
     type Key = ContentId;
 
@@ -128,6 +363,192 @@ mod test {
         }
     }
 
+    #[test]
+    fn into_bytes() {
+        let bytes = Bytes::from("foo\nbar\n");
+        let contents = FileContents::new_bytes(bytes.clone());
+        assert_eq!(contents.size(), bytes.len());
+        let out = contents.into_bytes();
+        assert_eq!(out, bytes);
+        // The buffer is moved out, not copied.
+        assert_eq!(out.as_ptr(), bytes.as_ptr());
+    }
+
+    #[test]
+    fn storage_class() {
+        let contents = FileContents::new_bytes("0123456789");
+        assert_eq!(contents.storage_class(9), StorageClass::Chunked);
+        assert_eq!(contents.storage_class(10), StorageClass::Inline);
+        assert_eq!(contents.storage_class(11), StorageClass::Inline);
+    }
+
+    #[test]
+    fn storage_form() {
+        let contents = FileContents::new_bytes("0123456789");
+        assert_eq!(
+            contents.clone().into_storage_form(10, 4),
+            StoredContents::Inline(contents.clone())
+        );
+        assert_eq!(
+            contents.clone().into_storage_form(9, 4),
+            StoredContents::Chunked(vec![
+                Bytes::from("0123"),
+                Bytes::from("4567"),
+                Bytes::from("89"),
+            ])
+        );
+        assert_eq!(
+            contents.into_storage_form(9, 5),
+            StoredContents::Chunked(vec![Bytes::from("01234"), Bytes::from("56789")])
+        );
+    }
+
+    fn read_all<R: Read>(mut reader: R, buf_size: usize) -> Vec<u8> {
+        let mut buf = vec![0; buf_size];
+        let mut out = Vec::new();
+        loop {
+            let len = reader.read(&mut buf).expect("in-memory reads should succeed");
+            if len == 0 {
+                return out;
+            }
+            out.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    #[test]
+    fn async_read() {
+        let contents = FileContents::new_bytes("0123456789");
+        for buf_size in 1..12 {
+            assert_eq!(
+                read_all(contents.clone().into_async_read(), buf_size),
+                b"0123456789"
+            );
+            assert_eq!(
+                read_all(contents.clone().into_storage_form(10, 4).into_async_read(), buf_size),
+                b"0123456789"
+            );
+            assert_eq!(
+                read_all(contents.clone().into_storage_form(9, 4).into_async_read(), buf_size),
+                b"0123456789"
+            );
+        }
+
+        let empty = FileContents::new_bytes("");
+        assert!(read_all(empty.into_async_read(), 4).is_empty());
+        assert!(read_all(StoredContents::Chunked(vec![]).into_async_read(), 4).is_empty());
+        let chunks = vec![Bytes::new(), Bytes::from("a"), Bytes::new()];
+        assert_eq!(
+            read_all(StoredContents::Chunked(chunks).into_async_read(), 4),
+            b"a"
+        );
+    }
+
+    #[test]
+    fn line_stats_lf() {
+        let stats = FileContents::new_bytes("foo\nbar\n").line_stats();
+        assert_eq!(
+            stats,
+            LineStats {
+                lines: 2,
+                lf: 2,
+                crlf: 0,
+                cr: 0,
+                ends_with_newline: true,
+                is_binary: false,
+            }
+        );
+    }
+
+    #[test]
+    fn line_stats_crlf() {
+        let stats = FileContents::new_bytes("foo\r\nbar\r\nbaz\n").line_stats();
+        assert_eq!(
+            stats,
+            LineStats {
+                lines: 3,
+                lf: 1,
+                crlf: 2,
+                cr: 0,
+                ends_with_newline: true,
+                is_binary: false,
+            }
+        );
+    }
+
+    #[test]
+    fn line_stats_no_trailing_newline() {
+        let stats = FileContents::new_bytes("foo\rbar\nbaz").line_stats();
+        assert_eq!(
+            stats,
+            LineStats {
+                lines: 3,
+                lf: 1,
+                crlf: 0,
+                cr: 1,
+                ends_with_newline: false,
+                is_binary: false,
+            }
+        );
+        assert_eq!(FileContents::new_bytes("").line_stats(), LineStats::default());
+    }
+
+    #[test]
+    fn line_stats_binary() {
+        let contents = FileContents::new_bytes(&b"foo\nbar\0\n"[..]);
+        assert!(contents.is_binary());
+        assert_eq!(
+            contents.line_stats(),
+            LineStats {
+                is_binary: true,
+                ..Default::default()
+            }
+        );
+        assert!(!FileContents::new_bytes("foo\n").is_binary());
+    }
+
+    #[test]
+    fn text_encoding() {
+        let ascii = FileContents::new_bytes("foo\nbar\n");
+        assert!(!ascii.is_binary());
+        assert_eq!(ascii.guess_text_encoding(), Some(Encoding::Utf8));
+
+        let early_nul = FileContents::new_bytes(&b"\x7fELF\x02\x01\x01\0"[..]);
+        assert!(early_nul.is_binary());
+
+        let utf8_bom = FileContents::new_bytes(&b"\xef\xbb\xbffoo\n"[..]);
+        assert!(!utf8_bom.is_binary());
+        assert_eq!(utf8_bom.guess_text_encoding(), Some(Encoding::Utf8));
+        assert_eq!(
+            FileContents::new_bytes(&b"\xff\xfef\0o\0"[..]).guess_text_encoding(),
+            Some(Encoding::Utf16Le)
+        );
+        assert_eq!(
+            FileContents::new_bytes(&b"\xfe\xff\0f\0o"[..]).guess_text_encoding(),
+            Some(Encoding::Utf16Be)
+        );
+        assert_eq!(FileContents::new_bytes(&b"caf\xe9"[..]).guess_text_encoding(), None);
+    }
+
+    #[test]
+    fn symlink_target() {
+        let target = FileContents::new_bytes("../lib/foo.so");
+        assert_eq!(target.as_symlink_target().unwrap(), b"../lib/foo.so");
+        let target = FileContents::new_bytes("/usr/bin/python");
+        assert_eq!(target.as_symlink_target().unwrap(), b"/usr/bin/python");
+        let target = FileContents::new_bytes(vec![b'a'; MAX_SYMLINK_TARGET_LEN]);
+        target.as_symlink_target().expect("maximum length target should be valid");
+
+        FileContents::new_bytes(&b"foo\0bar"[..])
+            .as_symlink_target()
+            .expect_err("unexpected OK - NUL byte");
+        FileContents::new_bytes("")
+            .as_symlink_target()
+            .expect_err("unexpected OK - empty");
+        FileContents::new_bytes(vec![b'a'; MAX_SYMLINK_TARGET_LEN + 1])
+            .as_symlink_target()
+            .expect_err("unexpected OK - too long");
+    }
+
     #[test]
     fn bad_thrift() {
         let thrift_fc = thrift::FileContents::UnknownField(-1);
@@ -77,6 +77,16 @@ impl Blake2 {
         Self::from_str(s.as_str())
     }
 
+    /// Compare with `other` in constant time, for when the result gates access to something and
+    /// `==` (which stops at the first differing byte) could leak timing information.
+    pub fn ct_eq(&self, other: &Self) -> bool {
+        let diff = self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        diff == 0
+    }
+
     pub fn to_hex(&self) -> AsciiString {
         let mut v = Vec::with_capacity(64);
         for &byte in self.as_ref() {
@@ -133,22 +143,25 @@ impl AsRef<[u8]> for Blake2 {
     }
 }
 
+/// Parse a `Blake2` from exactly 64 hex digits. Digits may be in either case, though `Display`
+/// and `to_hex` always produce lowercase.
 impl FromStr for Blake2 {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        if s.len() < 64 {
-            bail_err!(ErrorKind::InvalidBlake2Input(
-                "need at least 64 hex digits".into()
-            ));
+        if s.len() != 64 {
+            bail_err!(ErrorKind::InvalidBlake2Input(format!(
+                "need exactly 64 hex digits, got {}",
+                s.len()
+            )));
         }
 
         let mut ret = Blake2([0; 32]);
 
-        for idx in 0..ret.0.len() {
-            ret.0[idx] = match u8::from_str_radix(&s[(idx * 2)..(idx * 2 + 2)], 16) {
-                Ok(v) => v,
-                Err(_) => bail_err!(ErrorKind::InvalidBlake2Input("bad digit".into())),
+        for (idx, pair) in s.as_bytes().chunks(2).enumerate() {
+            ret.0[idx] = match (hex_digit(pair[0]), hex_digit(pair[1])) {
+                (Some(hi), Some(lo)) => (hi << 4) | lo,
+                _ => bail_err!(ErrorKind::InvalidBlake2Input("bad digit".into())),
             };
         }
 
@@ -156,6 +169,15 @@ impl FromStr for Blake2 {
     }
 }
 
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        b'A'...b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
 impl Display for Blake2 {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         Display::fmt(&self.to_hex(), fmt)
@@ -181,6 +203,102 @@ impl Arbitrary for Blake2 {
     }
 }
 
+/// The leading hex digits of a `Blake2` hash, as typed by users who abbreviate hashes.
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+pub struct HashPrefix {
+    // Only the first `nibbles` hex digits are meaningful; the rest are zero.
+    bytes: [u8; 32],
+    nibbles: usize,
+}
+
+impl HashPrefix {
+    /// The number of hex digits in this prefix.
+    #[inline]
+    pub fn num_digits(&self) -> usize {
+        self.nibbles
+    }
+
+    /// Whether `full` starts with this prefix.
+    pub fn matches(&self, full: &Blake2) -> bool {
+        let whole_bytes = self.nibbles / 2;
+        if self.bytes[..whole_bytes] != full.0[..whole_bytes] {
+            return false;
+        }
+        // With an odd number of digits, the last one is the high half of the next byte.
+        self.nibbles % 2 == 0 || self.bytes[whole_bytes] == full.0[whole_bytes] & 0xf0
+    }
+
+    /// The smallest hash that starts with this prefix.
+    pub fn min_bound(&self) -> Blake2 {
+        Blake2(self.bytes)
+    }
+
+    /// The largest hash that starts with this prefix. Together with `min_bound`, this gives the
+    /// inclusive range of hashes that the prefix matches.
+    pub fn max_bound(&self) -> Blake2 {
+        let mut bytes = self.bytes;
+        let whole_bytes = self.nibbles / 2;
+        if self.nibbles % 2 == 1 {
+            bytes[whole_bytes] |= 0x0f;
+            for byte in &mut bytes[whole_bytes + 1..] {
+                *byte = 0xff;
+            }
+        } else {
+            for byte in &mut bytes[whole_bytes..] {
+                *byte = 0xff;
+            }
+        }
+        Blake2(bytes)
+    }
+}
+
+/// Parse a `HashPrefix` from between 1 and 64 hex digits, in either case.
+impl FromStr for HashPrefix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() || s.len() > 64 {
+            bail_err!(ErrorKind::InvalidBlake2Input(format!(
+                "need between 1 and 64 hex digits for a prefix, got {}",
+                s.len()
+            )));
+        }
+
+        let mut ret = HashPrefix {
+            bytes: [0; 32],
+            nibbles: s.len(),
+        };
+
+        for (idx, c) in s.bytes().enumerate() {
+            let digit = match hex_digit(c) {
+                Some(digit) => digit,
+                None => bail_err!(ErrorKind::InvalidBlake2Input("bad digit".into())),
+            };
+            let shift = if idx % 2 == 0 { 4 } else { 0 };
+            ret.bytes[idx / 2] |= digit << shift;
+        }
+
+        Ok(ret)
+    }
+}
+
+impl Display for HashPrefix {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for idx in 0..self.nibbles {
+            let byte = self.bytes[idx / 2];
+            let digit = if idx % 2 == 0 { byte >> 4 } else { byte & 0xf };
+            write!(fmt, "{}", HEX_CHARS[digit as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for HashPrefix {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "HashPrefix({})", self)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -208,6 +326,22 @@ mod test {
         assert_eq!(nil, NILHASH);
     }
 
+    #[test]
+    fn chunked_update_abc() {
+        let mut single = Context::new(b"");
+        single.update(b"abcdef");
+
+        let mut chunked = Context::new(b"");
+        chunked.update(b"ab");
+        chunked.update(b"");
+        chunked.update(b"cde");
+        chunked.update(b"f");
+
+        let hash = chunked.finish();
+        assert_eq!(single.finish(), hash);
+        assert_ne!(hash, NILHASH);
+    }
+
     #[test]
     fn parse_ok() {
         assert_eq!(
@@ -261,6 +395,55 @@ mod test {
             .expect_err("unexpected OK - badchar end");
         Blake2::from_str("0e5751c026e543b2e8ab2eb06099daa1d1x5df47778f7787faab45cdf12fe3a")
             .expect_err("unexpected OK - badchar middle");
+        Blake2::from_str("0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a80")
+            .expect_err("unexpected OK - too long");
+        Blake2::from_str("+e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8")
+            .expect_err("unexpected OK - sign");
+        Blake2::from_str("\u{e9}5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a")
+            .expect_err("unexpected OK - non-ASCII");
+    }
+
+    #[test]
+    fn hash_prefix() {
+        let prefix = |s| HashPrefix::from_str(s).unwrap();
+
+        assert!(prefix("0e5").matches(&NILHASH));
+        assert!(prefix("0E57").matches(&NILHASH));
+        assert!(prefix("0").matches(&NILHASH));
+        assert!(prefix("0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8")
+            .matches(&NILHASH));
+        assert!(!prefix("0e4").matches(&NILHASH));
+        assert!(!prefix("0e6").matches(&NILHASH));
+        assert!(!prefix("0f57").matches(&NILHASH));
+        assert!(!prefix("0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a9")
+            .matches(&NILHASH));
+        assert!(prefix("000").matches(&NULL));
+        assert!(!prefix("0e5").matches(&NULL));
+
+        assert_eq!(
+            prefix("0e5").min_bound(),
+            Blake2::from_str("0e50000000000000000000000000000000000000000000000000000000000000")
+                .unwrap()
+        );
+        assert_eq!(
+            prefix("0e5").max_bound(),
+            Blake2::from_str("0e5fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+                .unwrap()
+        );
+        assert_eq!(
+            prefix("0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8").max_bound(),
+            NILHASH
+        );
+
+        assert_eq!(prefix("0E5").num_digits(), 3);
+        assert_eq!(format!("{}", prefix("0E5")), "0e5");
+
+        HashPrefix::from_str("").expect_err("unexpected OK - zero len");
+        HashPrefix::from_str("0e5x").expect_err("unexpected OK - badchar");
+        HashPrefix::from_str("+e5").expect_err("unexpected OK - sign");
+        HashPrefix::from_str(
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a80",
+        ).expect_err("unexpected OK - too long");
     }
 
     #[test]
@@ -282,6 +465,38 @@ mod test {
             TestResult::from_bool(h == sh)
         }
 
+        fn chunked_update(data: Vec<u8>, chunk_lens: Vec<usize>) -> bool {
+            let mut single = Context::new(b"key");
+            single.update(&data);
+
+            let mut chunked = Context::new(b"key");
+            let mut rest = &data[..];
+            for len in chunk_lens {
+                let len = ::std::cmp::min(len, rest.len());
+                chunked.update(&rest[..len]);
+                rest = &rest[len..];
+            }
+            chunked.update(rest);
+
+            single.finish() == chunked.finish()
+        }
+
+        fn ct_eq_matches_eq(h: Blake2, other: Blake2, idx: usize, flip: u8) -> bool {
+            let mut flipped = h;
+            flipped.0[idx % 32] ^= flip;
+            h.ct_eq(&h) && h.ct_eq(&other) == (h == other)
+                && h.ct_eq(&flipped) == (h == flipped)
+        }
+
+        fn hash_prefix_matches(h: Blake2, len: usize) -> bool {
+            let hex = h.to_hex();
+            let len = len % 64 + 1;
+            let prefix = HashPrefix::from_str(&hex.as_str()[..len]).unwrap();
+            prefix.matches(&h) && format!("{}", prefix) == &hex.as_str()[..len]
+                && prefix.min_bound() <= h && h <= prefix.max_bound()
+                && prefix.matches(&prefix.min_bound()) && prefix.matches(&prefix.max_bound())
+        }
+
         fn to_hex_roundtrip(h: Blake2) -> bool {
             let v = h.to_hex();
             let sh = Blake2::from_ascii_str(&v).unwrap();
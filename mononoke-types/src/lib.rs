@@ -14,12 +14,14 @@
 
 extern crate ascii;
 extern crate asyncmemo;
+extern crate base64;
 extern crate bincode;
 extern crate blake2;
 extern crate bytes;
 extern crate chrono;
 #[macro_use]
 extern crate failure_ext as failure;
+extern crate futures;
 extern crate heapsize;
 #[macro_use]
 extern crate heapsize_derive;
@@ -33,7 +35,10 @@ extern crate quickcheck;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate tokio_io;
 
+extern crate rust_crypto;
 extern crate rust_thrift;
 
 extern crate mononoke_types_thrift;
@@ -50,11 +55,12 @@ pub mod typed_hash;
 
 pub use blob::{Blob, BlobstoreBytes, BlobstoreValue, ChangesetBlob, ContentBlob};
 pub use bonsai_changeset::BonsaiChangeset;
-pub use datetime::DateTime;
-pub use file_change::{FileChange, FileType};
-pub use file_contents::FileContents;
-pub use path::{MPath, MPathElement, RepoPath};
-pub use typed_hash::{ChangesetId, ContentId, MononokeId};
+pub use datetime::{DateTime, DateTimeRange};
+pub use file_change::{FileChange, FileChangeEntry, FileType};
+pub use file_contents::{Encoding, FileContents, LineStats, StorageClass, StoredContents};
+pub use path::{MPath, MPathBuilder, MPathElement, RepoPath};
+pub use typed_hash::{ChangesetId, ChangesetIdPrefix, ContentId, HashAlgo, ManifestId, MononokeId,
+                     TaggedContentId};
 
 mod thrift {
     pub use mononoke_types_thrift::*;
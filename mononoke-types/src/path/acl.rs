@@ -0,0 +1,131 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use super::MPath;
+
+/// An access level. Levels are ordered, and each level includes the ones below it: `Write`
+/// access implies `Read` access.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Permission {
+    NoAccess,
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AclDecision {
+    Allow,
+    Deny,
+}
+
+/// A set of rules granting access levels to paths by prefix.
+///
+/// The rules that apply to a path are those for the longest prefix of that path that has any
+/// rules, so that a rule for a subdirectory overrides a rule for its parent. If there are
+/// several rules for the same prefix, the most restrictive one wins. Paths with no matching
+/// rules get the default access level.
+#[derive(Clone, Debug)]
+pub struct Acl {
+    rules: Vec<(MPath, Permission)>,
+    default: Permission,
+}
+
+impl Acl {
+    pub fn new<I>(rules: I, default: Permission) -> Self
+    where
+        I: IntoIterator<Item = (MPath, Permission)>,
+    {
+        Acl {
+            rules: rules.into_iter().collect(),
+            default,
+        }
+    }
+
+    /// The access level granted to `path`.
+    pub fn permission(&self, path: &MPath) -> Permission {
+        let mut best: Option<(usize, Permission)> = None;
+        for &(ref prefix, permission) in &self.rules {
+            if !prefix.is_prefix_of(path) {
+                continue;
+            }
+            let len = prefix.num_components();
+            best = match best {
+                Some((best_len, best_permission)) if best_len > len => {
+                    Some((best_len, best_permission))
+                }
+                Some((best_len, best_permission)) if best_len == len => {
+                    Some((len, ::std::cmp::min(best_permission, permission)))
+                }
+                _ => Some((len, permission)),
+            };
+        }
+        best.map_or(self.default, |(_, permission)| permission)
+    }
+
+    /// Whether `path` may be accessed at the `requested` level.
+    pub fn check(&self, path: &MPath, requested: Permission) -> AclDecision {
+        if self.permission(path) >= requested {
+            AclDecision::Allow
+        } else {
+            AclDecision::Deny
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(p: &str) -> MPath {
+        MPath::new(p).unwrap()
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let acl = Acl::new(
+            vec![
+                (path("foo"), Permission::Read),
+                (path("foo/bar"), Permission::Write),
+            ],
+            Permission::NoAccess,
+        );
+        assert_eq!(acl.check(&path("foo/x"), Permission::Read), AclDecision::Allow);
+        assert_eq!(acl.check(&path("foo/x"), Permission::Write), AclDecision::Deny);
+        assert_eq!(acl.check(&path("foo/bar/x"), Permission::Write), AclDecision::Allow);
+        // foo is not a path prefix of foobar.
+        assert_eq!(acl.check(&path("foobar"), Permission::Read), AclDecision::Deny);
+    }
+
+    #[test]
+    fn deny_over_allow() {
+        let acl = Acl::new(
+            vec![
+                (path("foo"), Permission::Write),
+                (path("foo/secret"), Permission::NoAccess),
+                (path("bar"), Permission::Write),
+                (path("bar"), Permission::Read),
+            ],
+            Permission::Read,
+        );
+        assert_eq!(acl.check(&path("foo/x"), Permission::Write), AclDecision::Allow);
+        assert_eq!(acl.check(&path("foo/secret/x"), Permission::Read), AclDecision::Deny);
+        // The more restrictive of two rules for the same prefix wins, regardless of order.
+        assert_eq!(acl.check(&path("bar/x"), Permission::Write), AclDecision::Deny);
+        assert_eq!(acl.check(&path("bar/x"), Permission::Read), AclDecision::Allow);
+    }
+
+    #[test]
+    fn default() {
+        let rules = vec![(path("foo"), Permission::Write)];
+        let acl = Acl::new(rules.clone(), Permission::NoAccess);
+        assert_eq!(acl.check(&path("baz"), Permission::Read), AclDecision::Deny);
+        let acl = Acl::new(rules, Permission::Read);
+        assert_eq!(acl.check(&path("baz"), Permission::Read), AclDecision::Allow);
+        assert_eq!(acl.check(&path("baz"), Permission::Write), AclDecision::Deny);
+        // Any access level is allowed to request no access.
+        assert_eq!(acl.check(&path("baz"), Permission::NoAccess), AclDecision::Allow);
+    }
+}
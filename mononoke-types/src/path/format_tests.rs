@@ -0,0 +1,85 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Round-trip tests covering every serialized form of `MPath`. New formats should be added to
+//! `formats()` so that they're checked both for round-tripping and against every other format.
+
+use bincode;
+use failure::SyncFailure;
+use rust_thrift::compact_protocol;
+
+use errors::*;
+use thrift;
+
+use super::MPath;
+
+struct Format {
+    name: &'static str,
+    encode: fn(&MPath) -> Vec<u8>,
+    decode: fn(&[u8]) -> Result<MPath>,
+}
+
+fn formats() -> Vec<Format> {
+    vec![
+        Format {
+            name: "bincode",
+            encode: |p| bincode::serialize(p).expect("serialize for MPath cannot fail"),
+            decode: |bytes| Ok(bincode::deserialize(bytes)?),
+        },
+        Format {
+            name: "thrift",
+            encode: |p| compact_protocol::serialize(&p.clone().into_thrift()).to_vec(),
+            decode: |bytes| {
+                let thrift_path: thrift::MPath = compact_protocol::deserialize(bytes)
+                    .map_err(SyncFailure::new)?;
+                MPath::from_thrift(thrift_path)
+            },
+        },
+        Format {
+            name: "bytes",
+            encode: |p| p.to_vec(),
+            decode: |bytes| MPath::new(bytes),
+        },
+        Format {
+            name: "length_prefixed",
+            encode: |p| {
+                let mut bytes = vec![];
+                p.write_length_prefixed(&mut bytes)
+                    .expect("writing to a Vec cannot fail");
+                bytes
+            },
+            decode: |mut bytes| MPath::read_length_prefixed(&mut bytes),
+        },
+    ]
+}
+
+quickcheck! {
+    fn roundtrip(p: MPath) -> bool {
+        formats().iter().all(|format| {
+            let decoded = (format.decode)(&(format.encode)(&p));
+            match decoded {
+                Ok(decoded) => decoded == p,
+                Err(err) => panic!("{} failed to decode: {}", format.name, err),
+            }
+        })
+    }
+
+    /// Bytes in one format must never decode as the same path in another format, so that
+    /// mixing up formats can't silently succeed.
+    fn cross_format(p: MPath) -> bool {
+        let formats = formats();
+        formats.iter().all(|encoder| {
+            let bytes = (encoder.encode)(&p);
+            formats
+                .iter()
+                .filter(|decoder| decoder.name != encoder.name)
+                .all(|decoder| match (decoder.decode)(&bytes) {
+                    Ok(decoded) => decoded != p,
+                    Err(_) => true,
+                })
+        })
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+
+use base64;
+use serde_json;
+
+use super::{MPath, MPathElement};
+
+#[derive(Serialize)]
+struct HistogramEntry {
+    component: String,
+    // Only present for components that aren't valid UTF-8, in which case `component` is the
+    // base64-encoded component.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    count: usize,
+}
+
+/// Render a histogram of path components (for example, of top-level directories) as a JSON
+/// array of `{"component": ..., "count": ...}` objects, sorted by descending count. Components
+/// with equal counts are sorted by their bytes, so the output is deterministic.
+///
+/// Components that aren't valid UTF-8 are base64-encoded, and tagged with
+/// `"encoding": "base64"`.
+pub fn histogram_to_json(hist: &HashMap<MPathElement, usize>) -> String {
+    let mut sorted: Vec<_> = hist.iter().collect();
+    sorted.sort_by(|&(elem1, count1), &(elem2, count2)| {
+        count2.cmp(count1).then_with(|| elem1.cmp(elem2))
+    });
+
+    let entries: Vec<_> = sorted
+        .into_iter()
+        .map(|(elem, count)| match String::from_utf8(elem.to_bytes()) {
+            Ok(component) => HistogramEntry {
+                component,
+                encoding: None,
+                count: *count,
+            },
+            Err(_) => HistogramEntry {
+                component: base64::encode(elem.as_bytes()),
+                encoding: Some("base64"),
+                count: *count,
+            },
+        })
+        .collect();
+
+    serde_json::to_string(&entries).expect("serializing a histogram cannot fail")
+}
+
+/// Count how often each byte value occurs across the serialized forms of `paths` (as returned
+/// by `MPath::to_vec`), including the `/` separators, e.g. to estimate how well paths would
+/// compress. The paths are scanned in place rather than serialized.
+pub fn byte_histogram(paths: &[MPath]) -> [u64; 256] {
+    let mut hist = [0u64; 256];
+    for path in paths {
+        for elem in &path.elements {
+            for b in elem.as_bytes() {
+                hist[*b as usize] += 1;
+            }
+        }
+        hist[b'/' as usize] += (path.num_components() - 1) as u64;
+    }
+    hist
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn byte_counts() {
+        let paths: Vec<_> = vec!["a/b/c", "aa/b", "c"]
+            .into_iter()
+            .map(|p| MPath::new(p).unwrap())
+            .collect();
+        let hist = byte_histogram(&paths);
+
+        // One separator between each pair of adjacent components.
+        let components: usize = paths.iter().map(MPath::num_components).sum();
+        assert_eq!(hist[b'/' as usize], (components - paths.len()) as u64);
+        assert_eq!(hist[b'a' as usize], 3);
+        assert_eq!(hist[b'b' as usize], 2);
+        assert_eq!(hist[b'c' as usize], 2);
+        let total: usize = paths.iter().map(MPath::len).sum();
+        assert_eq!(hist.iter().sum::<u64>(), total as u64);
+
+        assert!(byte_histogram(&[]).iter().all(|count| *count == 0));
+    }
+
+    quickcheck! {
+        fn byte_histogram_matches_to_vec(paths: Vec<MPath>) -> bool {
+            let mut expected = [0u64; 256];
+            for path in &paths {
+                for b in path.to_vec() {
+                    expected[b as usize] += 1;
+                }
+            }
+            byte_histogram(&paths)[..] == expected[..]
+        }
+    }
+
+    #[test]
+    fn histogram_json() {
+        let hist = hashmap! {
+            MPathElement::new(b"docs".to_vec()).unwrap() => 3,
+            MPathElement::new(b"src".to_vec()).unwrap() => 10,
+            MPathElement::new(b"\xff\xfe".to_vec()).unwrap() => 3,
+        };
+
+        assert_eq!(
+            histogram_to_json(&hist),
+            concat!(
+                r#"[{"component":"src","count":10},"#,
+                r#"{"component":"docs","count":3},"#,
+                r#"{"component":"//4=","encoding":"base64","count":3}]"#
+            )
+        );
+    }
+}
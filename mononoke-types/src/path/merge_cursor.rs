@@ -0,0 +1,127 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::iter::Peekable;
+
+use super::MPath;
+
+/// A cursor over a sorted sequence of paths, for merge-joining several such sequences. This is
+/// a `Peekable` with a couple of helpers that merge loops tend to need.
+pub struct PathMergeCursor<I: Iterator<Item = MPath>> {
+    inner: Peekable<I>,
+}
+
+impl<I: Iterator<Item = MPath>> PathMergeCursor<I> {
+    pub fn new<T: IntoIterator<IntoIter = I, Item = MPath>>(paths: T) -> Self {
+        PathMergeCursor {
+            inner: paths.into_iter().peekable(),
+        }
+    }
+
+    /// The next path, without consuming it.
+    #[inline]
+    pub fn peek(&mut self) -> Option<&MPath> {
+        self.inner.peek()
+    }
+
+    /// Whether there are no more paths.
+    #[inline]
+    pub fn is_done(&mut self) -> bool {
+        self.inner.peek().is_none()
+    }
+
+    /// Skip over paths for as long as `pred` holds, leaving the cursor at the first path for
+    /// which it doesn't. Returns the number of paths skipped.
+    pub fn advance_while<F>(&mut self, mut pred: F) -> usize
+    where
+        F: FnMut(&MPath) -> bool,
+    {
+        let mut skipped = 0;
+        while self.inner.peek().map_or(false, |path| pred(path)) {
+            self.inner.next();
+            skipped += 1;
+        }
+        skipped
+    }
+}
+
+impl<I: Iterator<Item = MPath>> Iterator for PathMergeCursor<I> {
+    type Item = MPath;
+
+    #[inline]
+    fn next(&mut self) -> Option<MPath> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cmp::Ordering;
+
+    fn paths(paths: &[&str]) -> Vec<MPath> {
+        paths.iter().map(|p| MPath::new(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn merge() {
+        let mut left = PathMergeCursor::new(paths(&["a", "b/c", "d", "f"]));
+        let mut right = PathMergeCursor::new(paths(&["b", "b/c", "e", "f", "g"]));
+
+        let mut merged = vec![];
+        loop {
+            let order = match (left.peek(), right.peek()) {
+                (Some(l), Some(r)) => l.cmp(r),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match order {
+                Ordering::Less => merged.push(("left", left.next().unwrap())),
+                Ordering::Greater => merged.push(("right", right.next().unwrap())),
+                Ordering::Equal => {
+                    right.next();
+                    merged.push(("both", left.next().unwrap()));
+                }
+            }
+        }
+
+        let expected: Vec<_> = vec![
+            ("left", "a"),
+            ("right", "b"),
+            ("both", "b/c"),
+            ("left", "d"),
+            ("right", "e"),
+            ("both", "f"),
+            ("right", "g"),
+        ].into_iter()
+            .map(|(side, p)| (side, MPath::new(p).unwrap()))
+            .collect();
+        assert_eq!(merged, expected);
+        assert!(left.is_done());
+        assert!(right.is_done());
+    }
+
+    #[test]
+    fn advance_while() {
+        let mut cursor = PathMergeCursor::new(paths(&["a", "b", "c/d", "e"]));
+        let target = MPath::new("c").unwrap();
+
+        assert_eq!(cursor.advance_while(|p| p < &target), 2);
+        assert_eq!(cursor.peek(), Some(&MPath::new("c/d").unwrap()));
+        // The predicate doesn't hold for the current path, so nothing is skipped.
+        assert_eq!(cursor.advance_while(|p| p < &target), 0);
+        assert_eq!(cursor.advance_while(|_| true), 2);
+        assert!(cursor.is_done());
+        assert_eq!(cursor.next(), None);
+    }
+}
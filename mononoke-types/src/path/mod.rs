@@ -0,0 +1,2343 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{From, TryFrom, TryInto};
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::iter::{once, Once};
+use std::mem;
+use std::slice::Iter;
+use std::str::FromStr;
+
+use asyncmemo::Weight;
+use base64;
+use bincode;
+use futures::{stream, Async, AsyncSink, Poll, Sink, Stream};
+use futures::sync::mpsc;
+use heapsize::HeapSizeOf;
+
+use quickcheck::{Arbitrary, Gen};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as DeError;
+
+use errors::*;
+use thrift;
+
+mod acl;
+#[cfg(test)]
+mod format_tests;
+mod histogram;
+mod merge_cursor;
+pub mod set_ops;
+mod validator;
+
+pub use self::acl::{Acl, AclDecision, Permission};
+pub use self::histogram::{byte_histogram, histogram_to_json};
+pub use self::merge_cursor::PathMergeCursor;
+pub use self::validator::{PathPolicy, PathValidator};
+
+lazy_static! {
+    pub static ref DOT: MPathElement = MPathElement::from_static(".");
+    pub static ref DOTDOT: MPathElement = MPathElement::from_static("..");
+}
+
+/// DOS device names that Windows reserves, with or without an extension.
+const WINDOWS_RESERVED_NAMES: &[&[u8]] = &[
+    b"CON", b"PRN", b"AUX", b"NUL", b"COM1", b"COM2", b"COM3", b"COM4", b"COM5", b"COM6", b"COM7",
+    b"COM8", b"COM9", b"LPT1", b"LPT2", b"LPT3", b"LPT4", b"LPT5", b"LPT6", b"LPT7", b"LPT8",
+    b"LPT9",
+];
+
+const WINDOWS_FORBIDDEN_CHARS: &[u8] = b"<>:\"\\|?*";
+
+impl Weight for RepoPath {
+    fn get_weight(&self) -> usize {
+        self.heap_size_of_children() + mem::size_of::<Self>()
+    }
+}
+
+/// A path or filename within Mononoke, with information about whether
+/// it's the root of the repo, a directory or a file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, HeapSizeOf)]
+#[derive(Serialize, Deserialize)]
+pub enum RepoPath {
+    // It is now *completely OK* to create a RepoPath directly. All MPaths are valid once
+    // constructed.
+    RootPath,
+    DirectoryPath(MPath),
+    FilePath(MPath),
+}
+
+impl RepoPath {
+    /// The root of the repo. In APIs that take an `Option<MPath>`, this corresponds to `None`.
+    pub const ROOT: RepoPath = RepoPath::RootPath;
+
+    #[inline]
+    pub fn root() -> Self {
+        RepoPath::RootPath
+    }
+
+    /// Convert an optional path, where `None` means the root, into a RepoPath for a file.
+    /// `into_mpath` is the inverse.
+    #[inline]
+    pub fn file_opt(path: Option<MPath>) -> Self {
+        match path {
+            None => RepoPath::RootPath,
+            Some(path) => RepoPath::FilePath(path),
+        }
+    }
+
+    /// Convert an optional path, where `None` means the root, into a RepoPath for a directory.
+    /// `into_mpath` is the inverse.
+    #[inline]
+    pub fn dir_opt(path: Option<MPath>) -> Self {
+        match path {
+            None => RepoPath::RootPath,
+            Some(path) => RepoPath::DirectoryPath(path),
+        }
+    }
+
+    pub fn dir<P>(path: P) -> Result<Self>
+    where
+        P: TryInto<MPath>,
+        Error: From<P::Error>,
+    {
+        let path = path.try_into()?;
+        Ok(RepoPath::DirectoryPath(path))
+    }
+
+    pub fn file<P>(path: P) -> Result<Self>
+    where
+        P: TryInto<MPath>,
+        Error: From<P::Error>,
+    {
+        let path = path.try_into()?;
+        Ok(RepoPath::FilePath(path))
+    }
+
+    /// Whether this path represents the root.
+    #[inline]
+    pub fn is_root(&self) -> bool {
+        match *self {
+            RepoPath::RootPath => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this path represents a directory that isn't the root.
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        match *self {
+            RepoPath::DirectoryPath(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this patch represents a tree (root or other directory).
+    #[inline]
+    pub fn is_tree(&self) -> bool {
+        match *self {
+            RepoPath::RootPath => true,
+            RepoPath::DirectoryPath(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this path represents a file.
+    #[inline]
+    pub fn is_file(&self) -> bool {
+        match *self {
+            RepoPath::FilePath(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Get the length of this repo path in bytes. `RepoPath::Root` has length 0.
+    pub fn len(&self) -> usize {
+        match *self {
+            RepoPath::RootPath => 0,
+            RepoPath::DirectoryPath(ref path) => path.len(),
+            RepoPath::FilePath(ref path) => path.len(),
+        }
+    }
+
+    pub fn mpath(&self) -> Option<&MPath> {
+        match *self {
+            RepoPath::RootPath => None,
+            RepoPath::DirectoryPath(ref path) => Some(path),
+            RepoPath::FilePath(ref path) => Some(path),
+        }
+    }
+
+    pub fn into_mpath(self) -> Option<MPath> {
+        match self {
+            RepoPath::RootPath => None,
+            RepoPath::DirectoryPath(path) => Some(path),
+            RepoPath::FilePath(path) => Some(path),
+        }
+    }
+
+    /// The directory containing this path, or `None` for the root. The parent of a path with a
+    /// single component is the root.
+    pub fn parent(&self) -> Option<RepoPath> {
+        match self.mpath() {
+            None => None,
+            Some(path) => Some(match path.parent() {
+                Some(parent) => RepoPath::DirectoryPath(parent),
+                None => RepoPath::RootPath,
+            }),
+        }
+    }
+
+    /// Serialize this RepoPath into a stable format that doesn't depend on how the type is
+    /// laid out: a tag byte (0 for the root, 1 for a directory, 2 for a file) followed by the
+    /// path bytes. Unlike `serialize`, this is safe to persist, e.g. in blobstore keys.
+    pub fn to_stable_bytes(&self) -> Vec<u8> {
+        let (tag, path) = match *self {
+            RepoPath::RootPath => (0, None),
+            RepoPath::DirectoryPath(ref path) => (1, Some(path)),
+            RepoPath::FilePath(ref path) => (2, Some(path)),
+        };
+        let mut bytes = Vec::with_capacity(1 + self.len());
+        bytes.push(tag);
+        if let Some(path) = path {
+            bytes.extend(path.to_vec());
+        }
+        bytes
+    }
+
+    /// Parse a RepoPath serialized by `to_stable_bytes`.
+    pub fn from_stable_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.split_first() {
+            Some((&0, path)) if path.is_empty() => Ok(RepoPath::RootPath),
+            Some((&0, _)) => bail_msg!("stable RepoPath: root path has trailing bytes"),
+            Some((&1, path)) => Ok(RepoPath::DirectoryPath(MPath::new(path)?)),
+            Some((&2, path)) => Ok(RepoPath::FilePath(MPath::new(path)?)),
+            Some((tag, _)) => bail_msg!("stable RepoPath: unknown tag {}", tag),
+            None => bail_msg!("stable RepoPath: input is empty"),
+        }
+    }
+
+    /// Serialize this RepoPath into a string. This shouldn't (yet) be considered stable if the
+    /// definition of RepoPath changes.
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("serialize for RepoPath cannot fail")
+    }
+
+    /// The number of bytes `serialize` would produce, computed without serializing. Like
+    /// `serialize`, this depends on the bincode layout: a 4-byte variant tag, followed for
+    /// non-root paths by an 8-byte element count and each element with an 8-byte length prefix.
+    pub fn serialized_len(&self) -> usize {
+        const TAG_LEN: usize = 4;
+        const LEN_PREFIX_LEN: usize = 8;
+        match self.mpath() {
+            None => TAG_LEN,
+            Some(path) => {
+                TAG_LEN + LEN_PREFIX_LEN
+                    + path.elements
+                        .iter()
+                        .map(|elem| LEN_PREFIX_LEN + elem.len())
+                        .sum::<usize>()
+            }
+        }
+    }
+
+    /// Serialize this RepoPath into a writer. This shouldn't (yet) be considered stable if the
+    /// definition of RepoPath changes.
+    pub fn serialize_into<W: Write>(&self, writer: &mut W) -> Result<()> {
+        Ok(bincode::serialize_into(writer, self)?)
+    }
+}
+
+impl Display for RepoPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RepoPath::RootPath => write!(f, "(root path)"),
+            RepoPath::DirectoryPath(ref path) => write!(f, "directory '{}'", path),
+            RepoPath::FilePath(ref path) => write!(f, "file '{}'", path),
+        }
+    }
+}
+
+/// This trait impl allows passing in a &RepoPath where `Into<RepoPath>` is requested.
+impl<'a> From<&'a RepoPath> for RepoPath {
+    fn from(path: &'a RepoPath) -> RepoPath {
+        path.clone()
+    }
+}
+
+/// An element of a path or filename within Mercurial.
+///
+/// Mercurial treats pathnames as sequences of bytes, but the manifest format
+/// assumes they cannot contain zero bytes. The bytes are not necessarily utf-8
+/// and so cannot be converted into a string (or - strictly speaking - be displayed).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, HeapSizeOf)]
+#[derive(Serialize, Deserialize)]
+pub struct MPathElement(Vec<u8>);
+
+impl MPathElement {
+    #[inline]
+    pub fn new<B: Into<Vec<u8>>>(element: B) -> Result<MPathElement> {
+        let element = element.into();
+        Self::verify(&element)?;
+        Ok(MPathElement(element))
+    }
+
+    /// Create an element from a string literal that is known to be valid, such as a constant.
+    /// The literal is only verified in debug builds, where an invalid one panics; release builds
+    /// trust it. Use `new` for anything that isn't a literal.
+    pub fn from_static(element: &'static str) -> MPathElement {
+        #[cfg(debug_assertions)]
+        {
+            if let Err(err) = Self::verify(element.as_bytes()) {
+                panic!("invalid static path element '{}': {}", element, err);
+            }
+        }
+        MPathElement(element.as_bytes().to_vec())
+    }
+
+    #[inline]
+    pub(crate) fn from_thrift(element: thrift::MPathElement) -> Result<MPathElement> {
+        Self::verify(&element.0).context(ErrorKind::InvalidThrift(
+            "MPathElement".into(),
+            "invalid path element".into(),
+        ))?;
+        Ok(MPathElement(element.0))
+    }
+
+    fn verify(p: &[u8]) -> Result<()> {
+        if p.is_empty() {
+            bail_err!(ErrorKind::InvalidPath(
+                "".into(),
+                "path elements cannot be empty".into()
+            ));
+        }
+        if p.contains(&0) {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "path elements cannot contain '\\0'".into(),
+            ));
+        }
+        if p.contains(&1) {
+            // MPath can not contain '\x01', in particular if mpath ends with '\x01'
+            // and it is part of move metadata, because key-value pairs are separated
+            // by '\n', you will get '\x01\n' which is also metadata separator.
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "path elements cannot contain '\\1'".into(),
+            ));
+        }
+        if p.contains(&b'/') {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "path elements cannot contain '/'".into(),
+            ));
+        }
+        if p.contains(&b'\n') {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "path elements cannot contain '\\n'".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that `p` can be used as a file name on Windows: it must not end with a space or a
+    /// dot, must not contain any of `<>:"\|?*`, and must not be a reserved DOS device name
+    /// such as `CON` or `nul.txt`.
+    ///
+    /// This is separate from the checks in `MPathElement::new`, since existing repos may
+    /// already contain such names.
+    pub fn verify_windows(p: &[u8]) -> Result<()> {
+        let invalid = |msg: &str| {
+            ErrorKind::InvalidPath(String::from_utf8_lossy(p).into_owned(), msg.into())
+        };
+        match p.last() {
+            Some(&b' ') => bail_err!(invalid("Windows file names cannot end with ' '")),
+            Some(&b'.') => bail_err!(invalid("Windows file names cannot end with '.'")),
+            _ => (),
+        }
+        if let Some(c) = p.iter().find(|c| WINDOWS_FORBIDDEN_CHARS.contains(c)) {
+            bail_err!(invalid(&format!(
+                "Windows file names cannot contain '{}'",
+                *c as char
+            )));
+        }
+        // Reserved names are reserved regardless of extension, so only look at the part
+        // before the first dot.
+        let stem = p.split(|c| *c == b'.').next().unwrap_or(&[]);
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(stem))
+        {
+            bail_err!(invalid(&format!(
+                "'{}' is a reserved name on Windows",
+                String::from_utf8_lossy(stem)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether this element can be used as a file name on Windows. See
+    /// `MPathElement::verify_windows`.
+    pub fn is_windows_safe(&self) -> bool {
+        Self::verify_windows(&self.0).is_ok()
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    pub fn extend(&mut self, toappend: &[u8]) {
+        self.0.extend(toappend.iter());
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub(crate) fn into_thrift(self) -> thrift::MPathElement {
+        thrift::MPathElement(self.0)
+    }
+}
+
+impl From<MPathElement> for MPath {
+    fn from(element: MPathElement) -> Self {
+        MPath {
+            elements: vec![element],
+        }
+    }
+}
+
+/// A path or filename within Mononoke (typically within manifests or changegroups).
+///
+/// This is called `MPath` so that it can be differentiated from `std::path::Path`.
+///
+/// For human-readable formats such as JSON, an `MPath` is serialized as its slash-joined string,
+/// or as `{"base64": ...}` if it isn't valid UTF-8. Other formats such as bincode use the
+/// compact representation: the list of elements.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, HeapSizeOf)]
+pub struct MPath {
+    elements: Vec<MPathElement>,
+}
+
+impl MPath {
+    pub fn new<P: AsRef<[u8]>>(p: P) -> Result<MPath> {
+        let p = p.as_ref();
+        Self::verify(p)?;
+        let elements: Vec<_> = p.split(|c| *c == b'/')
+            .filter(|e| !e.is_empty())
+            .map(|e| {
+                // These instances have already been checked to contain null bytes and also
+                // are split on '/' bytes and non-empty, so they're valid by construction. Skip the
+                // verification in MPathElement::new.
+                MPathElement(e.into())
+            })
+            .collect();
+        if elements.is_empty() {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "path cannot be empty".into()
+            ));
+        }
+        Ok(MPath { elements })
+    }
+
+    /// Like `new`, but resolves `.` and `..` components: `.` components are dropped, and each
+    /// `..` removes the component before it. It is an error for `..` to go above the root, or
+    /// for the normalized path to be empty.
+    pub fn new_normalized<P: AsRef<[u8]>>(p: P) -> Result<MPath> {
+        let p = p.as_ref();
+        Self::verify(p)?;
+        let elements = Self::resolve_dots(p.split(|c| *c == b'/').filter(|e| !e.is_empty()), p)?;
+        Ok(MPath { elements })
+    }
+
+    /// Resolve `.` and `..` components in this path, as `new_normalized` does. Most paths don't
+    /// have any, so this only allocates a new path if something needs to change.
+    pub fn normalize(&self) -> Result<Cow<MPath>> {
+        if !self.elements
+            .iter()
+            .any(|elem| *elem == *DOT || *elem == *DOTDOT)
+        {
+            return Ok(Cow::Borrowed(self));
+        }
+        let elements =
+            Self::resolve_dots(self.elements.iter().map(MPathElement::as_bytes), &self.to_vec())?;
+        Ok(Cow::Owned(MPath { elements }))
+    }
+
+    /// Build the elements of a normalized path from its non-empty components, which must
+    /// already be valid elements. `path` is only used for error messages.
+    fn resolve_dots<'a, I>(components: I, path: &[u8]) -> Result<Vec<MPathElement>>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut elements: Vec<MPathElement> = vec![];
+        for e in components {
+            if e == DOT.as_bytes() {
+                continue;
+            }
+            if e == DOTDOT.as_bytes() {
+                if elements.pop().is_none() {
+                    bail_err!(ErrorKind::InvalidPath(
+                        String::from_utf8_lossy(path).into_owned(),
+                        "'..' cannot go above the root".into()
+                    ));
+                }
+                continue;
+            }
+            // As in MPath::new, these are valid by construction.
+            elements.push(MPathElement(e.into()));
+        }
+        if elements.is_empty() {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(path).into_owned(),
+                "path cannot be empty".into()
+            ));
+        }
+        Ok(elements)
+    }
+
+    pub(crate) fn from_thrift(mpath: thrift::MPath) -> Result<MPath> {
+        let elements: Result<Vec<_>> = mpath
+            .0
+            .into_iter()
+            .map(|elem| MPathElement::from_thrift(elem))
+            .collect();
+        Ok(MPath {
+            elements: elements?,
+        })
+    }
+
+    fn verify(p: &[u8]) -> Result<()> {
+        if p.contains(&0) {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "paths cannot contain '\\0'".into(),
+            ));
+        }
+        if p.contains(&1) {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "paths cannot contain '\\1'".into(),
+            ));
+        }
+        if p.contains(&b'\n') {
+            bail_err!(ErrorKind::InvalidPath(
+                String::from_utf8_lossy(p).into_owned(),
+                "paths cannot contain '\\n'".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn join<'a, Elements: IntoIterator<Item = &'a MPathElement>>(
+        &self,
+        another: Elements,
+    ) -> MPath {
+        let mut newelements = self.elements.clone();
+        newelements.extend(
+            another
+                .into_iter()
+                .filter(|elem| !elem.0.is_empty())
+                .cloned(),
+        );
+        MPath {
+            elements: newelements,
+        }
+    }
+
+    pub fn join_element(&self, element: Option<&MPathElement>) -> MPath {
+        match element {
+            Some(element) => self.join(element),
+            None => self.clone(),
+        }
+    }
+
+    pub fn join_opt<'a, Elements: IntoIterator<Item = &'a MPathElement>>(
+        path: Option<&Self>,
+        another: Elements,
+    ) -> Option<Self> {
+        match path {
+            Some(path) => Some(path.join(another)),
+            None => {
+                let elements: Vec<MPathElement> = another
+                    .into_iter()
+                    .filter(|elem| !elem.0.is_empty())
+                    .cloned()
+                    .collect();
+                if elements.is_empty() {
+                    None
+                } else {
+                    Some(MPath { elements })
+                }
+            }
+        }
+    }
+
+    pub fn join_opt_element(path: Option<&Self>, element: &MPathElement) -> Self {
+        match path {
+            Some(path) => path.join_element(Some(element)),
+            None => MPath {
+                elements: vec![element.clone()],
+            },
+        }
+    }
+
+    pub fn join_element_opt(path: Option<&Self>, element: Option<&MPathElement>) -> Option<Self> {
+        match element {
+            Some(element) => Self::join_opt(path, element),
+            None => path.cloned(),
+        }
+    }
+
+    pub fn iter_opt(path: Option<&Self>) -> Iter<MPathElement> {
+        match path {
+            Some(path) => path.into_iter(),
+            None => [].into_iter(),
+        }
+    }
+
+    pub fn into_iter_opt(path: Option<Self>) -> ::std::vec::IntoIter<MPathElement> {
+        match path {
+            Some(path) => path.into_iter(),
+            None => (vec![]).into_iter(),
+        }
+    }
+
+    /// The number of components in this path.
+    pub fn num_components(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// How deep this path is in the tree: 1 for a top-level file or directory. This is the same
+    /// as `num_components`.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.num_components()
+    }
+
+    /// Iterate over the components of this path, along with the zero-based depth of each
+    /// component. Useful for rendering indented tree views.
+    pub fn enumerate_components(&self) -> impl Iterator<Item = (usize, &MPathElement)> {
+        self.elements.iter().enumerate()
+    }
+
+    /// Iterate over the components of this path in reverse order, starting with the basename.
+    pub fn reverse_components(&self) -> impl Iterator<Item = &MPathElement> {
+        self.elements.iter().rev()
+    }
+
+    /// The number of leading components that are common.
+    pub fn common_components<'a, E: IntoIterator<Item = &'a MPathElement>>(
+        &self,
+        other: E,
+    ) -> usize {
+        self.elements
+            .iter()
+            .zip(other)
+            .take_while(|&(e1, e2)| e1 == e2)
+            .count()
+    }
+
+    /// The longest directory or file path that is a prefix of both this path and `other`, or
+    /// `None` if their first components differ.
+    pub fn common_prefix(&self, other: &MPath) -> Option<MPath> {
+        match self.common_components(other) {
+            0 => None,
+            n => Some(MPath::from_elements(self.elements[..n].iter())),
+        }
+    }
+
+    /// Whether this path is a path prefix of the given path.
+    /// `foo` is a prefix of `foo/bar`, but not of `foo1`.
+    #[inline]
+    pub fn is_prefix_of<'a, E: IntoIterator<Item = &'a MPathElement>>(&self, other: E) -> bool {
+        self.common_components(other.into_iter()) == self.num_components()
+    }
+
+    /// Whether this path starts with the components of `prefix`, compared whole: `foo/bar`
+    /// starts with `foo`, but not with `fo`. This is `is_prefix_of` the other way around,
+    /// named to match `std::path::Path`.
+    pub fn starts_with<'a, E: IntoIterator<Item = &'a MPathElement>>(&self, prefix: E) -> bool {
+        let mut elements = self.elements.iter();
+        prefix.into_iter().all(|elem| elements.next() == Some(elem))
+    }
+
+    /// Whether this path ends with the components of `suffix`, compared whole: `foo/bar` ends
+    /// with `bar`, but not with `ar`.
+    pub fn ends_with<'a, E: IntoIterator<Item = &'a MPathElement>>(&self, suffix: E) -> bool {
+        let suffix: Vec<_> = suffix.into_iter().collect();
+        suffix.len() <= self.elements.len()
+            && self.elements[self.elements.len() - suffix.len()..]
+                .iter()
+                .zip(suffix)
+                .all(|(e1, e2)| e1 == e2)
+    }
+
+    /// The exclusive upper bound of the range of serialized paths (as returned by `to_vec`) that
+    /// are descendants of this path, for range scans over stores ordered by key bytes.
+    ///
+    /// Every descendant of `foo` serializes to `foo/...`, so the range is `foo/` (inclusive) up
+    /// to `foo0` (exclusive), as `0` is the byte after `/`. Appending to the last component
+    /// rather than incrementing its final byte means that a successor always exists, and that
+    /// siblings like `foo0` and `foo.txt` stay outside the range.
+    ///
+    /// Note that this bound is in byte order, not `MPath` order: in `MPath` order, `foo.txt`
+    /// sorts between `foo/bar` and `foo0`.
+    pub fn prefix_successor(&self) -> MPath {
+        let mut elements = self.elements.clone();
+        elements
+            .last_mut()
+            .expect("MPaths have at least one component")
+            .0
+            .push(b'/' + 1);
+        MPath { elements }
+    }
+
+    /// This path relative to `prefix`, or `None` if `prefix` isn't a path prefix of this path.
+    /// Stripping a path from itself also returns `None`, since empty MPaths can't be
+    /// represented.
+    pub fn strip_prefix(&self, prefix: &MPath) -> Option<MPath> {
+        if prefix.num_components() < self.num_components() && prefix.is_prefix_of(self) {
+            Some(MPath::from_elements(
+                self.elements[prefix.num_components()..].iter(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Compare paths, treating a directory as equal to an index file in it. For example, with
+    /// `index.html` in `index_names`, `foo` and `foo/index.html` are equal. This doesn't affect
+    /// `==`.
+    pub fn eq_with_index(&self, other: &MPath, index_names: &[MPathElement]) -> bool {
+        let is_index_of = |file: &MPath, dir: &MPath| {
+            file.num_components() == dir.num_components() + 1 && dir.is_prefix_of(file)
+                && index_names.contains(file.basename())
+        };
+        self == other || is_index_of(self, other) || is_index_of(other, self)
+    }
+
+    /// Whether every component of this path can be used as a file name on Windows. See
+    /// `MPathElement::is_windows_safe`.
+    pub fn is_windows_safe(&self) -> bool {
+        self.elements.iter().all(MPathElement::is_windows_safe)
+    }
+
+    /// The final component of this path.
+    pub fn basename(&self) -> &MPathElement {
+        self.elements
+            .last()
+            .expect("MPaths have at least one component")
+    }
+
+    /// Split the basename into stem and extension at its last `.`, following the semantics of
+    /// `std::path::Path`: a leading `.` (as in `.gitignore`) doesn't start an extension.
+    fn split_extension(&self) -> (&[u8], Option<&[u8]>) {
+        let name = self.basename().as_bytes();
+        match name.iter().rposition(|c| *c == b'.') {
+            Some(0) | None => (name, None),
+            Some(idx) => (&name[..idx], Some(&name[idx + 1..])),
+        }
+    }
+
+    /// The extension of the basename: the bytes after its last `.`, or `None` if it has no `.`
+    /// other than a leading one. For example, the extension of `foo.tar.gz` is `gz`.
+    pub fn extension(&self) -> Option<&[u8]> {
+        self.split_extension().1
+    }
+
+    /// The basename without its extension. For example, the stem of `foo.tar.gz` is `foo.tar`,
+    /// and the stem of `.gitignore` is `.gitignore`.
+    pub fn file_stem(&self) -> &[u8] {
+        self.split_extension().0
+    }
+
+    /// Encode this path as a single path element, for example to store it as one file name in a
+    /// flat directory. Separators are escaped as `%2F`, and the escape character itself as
+    /// `%25`. `from_flat_name` is the inverse.
+    pub fn to_flat_name(&self) -> MPathElement {
+        let mut flat = Vec::with_capacity(self.len());
+        for (idx, elem) in self.elements.iter().enumerate() {
+            if idx > 0 {
+                flat.extend_from_slice(b"%2F");
+            }
+            for b in &elem.0 {
+                match *b {
+                    b'%' => flat.extend_from_slice(b"%25"),
+                    b => flat.push(b),
+                }
+            }
+        }
+        // This is valid by construction: it's non-empty, has no '/', and the other bytes come
+        // from valid elements.
+        MPathElement(flat)
+    }
+
+    /// Decode a path encoded by `to_flat_name`.
+    pub fn from_flat_name(flat: &MPathElement) -> Result<MPath> {
+        let invalid = |msg: &str| {
+            ErrorKind::InvalidPath(
+                String::from_utf8_lossy(flat.as_bytes()).into_owned(),
+                msg.into(),
+            )
+        };
+
+        let mut elements = vec![];
+        let mut current = vec![];
+        let mut bytes = flat.as_bytes().iter();
+        while let Some(b) = bytes.next() {
+            if *b != b'%' {
+                current.push(*b);
+                continue;
+            }
+            let escape = (bytes.next(), bytes.next());
+            match escape {
+                (Some(&b'2'), Some(&b'5')) => current.push(b'%'),
+                (Some(&b'2'), Some(&b'F')) => {
+                    elements.push(MPathElement::new(current).context(invalid("empty component"))?);
+                    current = vec![];
+                }
+                _ => bail_err!(invalid("invalid escape sequence")),
+            }
+        }
+        elements.push(MPathElement::new(current).context(invalid("empty component"))?);
+        Ok(MPath { elements })
+    }
+
+    /// Create a new path with the number of leading components specified.
+    pub fn take_prefix_components(&self, components: usize) -> Result<Option<MPath>> {
+        match components {
+            0 => Ok(None),
+            x if x > self.num_components() => bail_msg!(
+                "taking {} components but path only has {}",
+                components,
+                self.num_components()
+            ),
+            _ => Ok(Some(MPath {
+                elements: self.elements[..components].to_vec(),
+            })),
+        }
+    }
+
+    pub fn generate<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_vec())
+    }
+
+    /// Write this path preceded by its length as a big-endian `u32`, so that it can be read back
+    /// from a stream with `read_length_prefixed`.
+    pub fn write_length_prefixed<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let len = self.len();
+        if len > u32::max_value() as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("path of length {} is too long to length-prefix", len),
+            ));
+        }
+        let len = len as u32;
+        out.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8])?;
+        self.generate(out)
+    }
+
+    /// Read a path written by `write_length_prefixed`. The declared length must match the
+    /// bytes that follow exactly: a frame whose content is shorter than its prefix, or which
+    /// doesn't round-trip to a path of the declared length (e.g. because of repeated slashes),
+    /// is rejected.
+    pub fn read_length_prefixed<R: Read>(input: &mut R) -> Result<MPath> {
+        let mut prefix = [0u8; 4];
+        input.read_exact(&mut prefix)?;
+        let len = prefix
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | (*b as usize));
+
+        let mut buf = Vec::with_capacity(cmp::min(len, 4096));
+        input.take(len as u64).read_to_end(&mut buf)?;
+        let invalid = |buf: &[u8], msg: String| {
+            ErrorKind::InvalidPath(String::from_utf8_lossy(buf).into_owned(), msg)
+        };
+        if buf.len() != len {
+            bail_err!(invalid(
+                &buf,
+                format!("length prefix is {}, but only {} bytes follow", len, buf.len())
+            ));
+        }
+
+        let path = MPath::new(&buf)?;
+        if path.len() != len {
+            bail_err!(invalid(
+                &buf,
+                format!(
+                    "length prefix is {}, but the path has length {}",
+                    len,
+                    path.len()
+                )
+            ));
+        }
+        Ok(path)
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let ret: Vec<_> = self.elements.iter().map(|e| e.0.as_ref()).collect();
+        ret.join(&b'/')
+    }
+
+    /// The length of this path, including any slashes in it.
+    pub fn len(&self) -> usize {
+        // n elements means n-1 slashes
+        let slashes = self.elements.len() - 1;
+        let elem_len: usize = self.elements.iter().map(|elem| elem.len()).sum();
+        slashes + elem_len
+    }
+
+    /// Create a path from elements that are already valid, e.g. because they were deserialized,
+    /// without joining and re-splitting them. This fails if there are no elements.
+    pub fn from_elements_checked<I>(elements: I) -> Result<MPath>
+    where
+        I: IntoIterator<Item = MPathElement>,
+    {
+        let elements: Vec<_> = elements.into_iter().collect();
+        if elements.is_empty() {
+            bail_err!(ErrorKind::InvalidPath(
+                "".into(),
+                "path must have at least one element".into()
+            ));
+        }
+        Ok(MPath { elements })
+    }
+
+    // Private because it does not validate elements - you must ensure that it's non-empty
+    fn from_elements<'a, I>(elements: I) -> Self
+    where
+        I: Iterator<Item = &'a MPathElement>,
+    {
+        Self {
+            elements: elements.cloned().collect(),
+        }
+    }
+
+    /// The directory containing this path, or `None` if this path has a single component (i.e.
+    /// its parent is the repo root).
+    pub fn parent(&self) -> Option<MPath> {
+        match self.elements.split_last() {
+            Some((_, dirname_elements)) if !dirname_elements.is_empty() => {
+                Some(MPath::from_elements(dirname_elements.iter()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Iterate over this path and then each of its parent directories in turn, up to its
+    /// top-level directory.
+    pub fn ancestors<'a>(&'a self) -> impl Iterator<Item = MPath> + 'a {
+        (1..self.num_components() + 1)
+            .rev()
+            .map(move |depth| MPath::from_elements(self.elements[..depth].iter()))
+    }
+
+    /// Check that no two elements of this path share the same underlying buffer. Elements own
+    /// their bytes, so this always holds today; it exists as a safety net in case elements move
+    /// to a shared buffer type such as `Bytes`, where two logically distinct elements could end
+    /// up aliasing each other.
+    ///
+    /// This is only checked in debug builds, and is a no-op in release builds.
+    pub fn assert_no_aliasing(&self) {
+        #[cfg(debug_assertions)]
+        {
+            let mut ptrs: Vec<*const u8> = self.elements.iter().map(|e| e.0.as_ptr()).collect();
+            ptrs.sort_unstable();
+            for pair in ptrs.windows(2) {
+                assert!(
+                    pair[0] != pair[1],
+                    "path '{}' has elements sharing a buffer",
+                    self
+                );
+            }
+        }
+    }
+
+    /// Split an MPath into dirname (if possible) and file name
+    pub fn split_dirname(&self) -> (Option<MPath>, &MPathElement) {
+        let (filename, dirname_elements) = self.elements
+            .split_last()
+            .expect("MPaths should never be empty");
+
+        if dirname_elements.is_empty() {
+            (None, filename)
+        } else {
+            (
+                Some(MPath::from_elements(dirname_elements.iter())),
+                filename,
+            )
+        }
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::MPath {
+        thrift::MPath(
+            self.elements
+                .into_iter()
+                .map(|elem| elem.into_thrift())
+                .collect(),
+        )
+    }
+}
+
+/// Builds an `MPath` one element at a time. Elements are validated when they're created, so
+/// this avoids re-validating the whole path as `MPath::new` would.
+#[derive(Clone, Debug, Default)]
+pub struct MPathBuilder {
+    elements: Vec<MPathElement>,
+}
+
+impl MPathBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, element: MPathElement) {
+        self.elements.push(element);
+    }
+
+    /// Build the path. Fails if no elements were pushed, since empty MPaths can't be
+    /// represented.
+    pub fn build(self) -> Result<MPath> {
+        if self.elements.is_empty() {
+            bail_err!(ErrorKind::InvalidPath(
+                "".into(),
+                "path cannot be empty".into()
+            ));
+        }
+        Ok(MPath {
+            elements: self.elements,
+        })
+    }
+}
+
+/// Check that a sorted list of MPaths is path-conflict-free. This means that
+/// no path in the list is a directory of another path.
+pub fn check_pcf<'a, I>(sorted_paths: I) -> Result<()>
+where
+    I: IntoIterator<Item = &'a MPath>,
+{
+    let mut last_path: Option<&MPath> = None;
+    // The key observation to make here is that in a sorted list, "foo" will always appear before
+    // "foo/bar", which in turn will always appear before "foo1".
+    // The loop invariant is that last_path at any point has no prefixes in the list.
+    for path in sorted_paths {
+        if let Some(last_path) = last_path {
+            if last_path.is_prefix_of(path) {
+                bail_err!(ErrorKind::NotPathPrefixFree(
+                    last_path.clone(),
+                    path.clone(),
+                ));
+            }
+        }
+        last_path = Some(path);
+    }
+
+    Ok(())
+}
+
+/// Like `check_pcf`, but additionally check that no two paths would collide on a
+/// case-insensitive filesystem, i.e. that no two of them have directories or file names that
+/// differ only by ASCII case. For example, `Foo/bar` and `foo/baz` conflict, since `Foo` and
+/// `foo` would be the same directory.
+pub fn check_pcf_case_insensitive<'a, I>(sorted_paths: I) -> Result<()>
+where
+    I: IntoIterator<Item = &'a MPath>,
+{
+    let sorted_paths: Vec<_> = sorted_paths.into_iter().collect();
+    check_pcf(sorted_paths.iter().cloned())?;
+
+    // Map from each case-folded directory or file path seen so far to the first path that
+    // introduced it. Two paths whose case-folded prefixes are equal must have exactly equal
+    // prefixes.
+    let mut seen: HashMap<Vec<u8>, &MPath> = HashMap::new();
+    for path in sorted_paths {
+        for depth in 1..path.num_components() + 1 {
+            let folded = MPath::from_elements(path.elements[..depth].iter())
+                .to_vec()
+                .to_ascii_lowercase();
+            match seen.get(&folded) {
+                Some(other) => {
+                    if other.elements[..depth] != path.elements[..depth] {
+                        bail_err!(ErrorKind::CaseConflict((*other).clone(), path.clone()));
+                    }
+                }
+                None => {
+                    seen.insert(folded, path);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Split a sorted list of paths into `n` contiguous, roughly equal partitions, for example to
+/// distribute a manifest walk across workers.
+///
+/// Each split point is chosen from a window around the ideal (equal-sized) split point so that
+/// as few directories as possible are split across partitions: a split between two different
+/// top-level directories is preferred over one between two subdirectories of the same
+/// directory, and so on. Among equally good split points, the one closest to the ideal point
+/// is chosen.
+///
+/// Every returned partition is non-empty, so fewer than `n` partitions are returned if there
+/// are fewer than `n` paths.
+pub fn partition_balanced(sorted: &[MPath], n: usize) -> Vec<&[MPath]> {
+    let len = sorted.len();
+    let n = cmp::min(n, len);
+    if n == 0 {
+        return vec![];
+    }
+
+    let slack = len / (2 * n);
+    let mut partitions = Vec::with_capacity(n);
+    let mut start = 0;
+    for i in 1..n {
+        let ideal = i * len / n;
+        // Leave at least one path for each of the remaining partitions.
+        let max_split = len - (n - i);
+        let lo = cmp::max(start + 1, ideal.saturating_sub(slack));
+        let hi = cmp::min(max_split, cmp::max(ideal + slack, start + 1));
+        let split = (lo..hi + 1)
+            .min_by_key(|&split| {
+                let shared = sorted[split - 1].common_components(&sorted[split]);
+                let distance = if split > ideal {
+                    split - ideal
+                } else {
+                    ideal - split
+                };
+                (shared, distance)
+            })
+            .expect("split window is never empty");
+        partitions.push(&sorted[start..split]);
+        start = split;
+    }
+    partitions.push(&sorted[start..]);
+    partitions
+}
+
+/// Remove duplicates from a sorted stream of paths. Since duplicates in a sorted stream are
+/// adjacent, only the previous path needs to be kept.
+pub fn dedup_sorted<S>(s: S) -> impl Stream<Item = MPath, Error = S::Error>
+where
+    S: Stream<Item = MPath>,
+{
+    let mut last: Option<MPath> = None;
+    s.filter_map(move |path| {
+        if last.as_ref() == Some(&path) {
+            None
+        } else {
+            last = Some(path.clone());
+            Some(path)
+        }
+    })
+}
+
+/// How many paths of a group `group_by_top_level` holds before waiting for them to be consumed.
+const GROUP_BUFFER_SIZE: usize = 100;
+
+/// Split a sorted stream of paths into one stream per top-level component, e.g. to hand each
+/// top-level directory to a separate worker. Since the input is sorted, all the paths under a
+/// component are adjacent, so each group's stream is emitted as soon as its first path arrives,
+/// and is fed through a bounded channel as the rest of its paths arrive.
+///
+/// The groups' streams only make progress while the returned stream is being polled, so it must
+/// keep being polled while they are consumed, e.g. by spawning a task per group. If a group's
+/// stream is dropped, the rest of its paths are skipped. If the input fails, the error is
+/// returned from the outer stream, and the group in progress ends early.
+pub fn group_by_top_level<S>(
+    s: S,
+) -> impl Stream<
+    Item = (MPathElement, Box<Stream<Item = MPath, Error = S::Error> + Send>),
+    Error = S::Error,
+>
+where
+    S: Stream<Item = MPath>,
+    S::Error: Send + 'static,
+{
+    GroupByTopLevel {
+        input: s.fuse(),
+        current: None,
+        pending: None,
+    }
+}
+
+struct GroupByTopLevel<S> {
+    input: stream::Fuse<S>,
+    // The top-level component of the group in progress, and the sender feeding its stream.
+    current: Option<(MPathElement, mpsc::Sender<MPath>)>,
+    // A path of the group in progress that hasn't been sent to the group's stream yet.
+    pending: Option<MPath>,
+}
+
+impl<S> Stream for GroupByTopLevel<S>
+where
+    S: Stream<Item = MPath>,
+    S::Error: Send + 'static,
+{
+    type Item = (MPathElement, Box<Stream<Item = MPath, Error = S::Error> + Send>);
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let path = match self.pending.take() {
+                Some(path) => path,
+                None => match self.input.poll() {
+                    Ok(Async::Ready(Some(path))) => path,
+                    Ok(Async::Ready(None)) => {
+                        // Dropping the sender ends the last group's stream.
+                        self.current = None;
+                        return Ok(Async::Ready(None));
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        self.current = None;
+                        return Err(err);
+                    }
+                },
+            };
+
+            if let Some((ref top, ref mut sender)) = self.current {
+                if path.elements[0] == *top {
+                    match sender.start_send(path) {
+                        Ok(AsyncSink::Ready) => {}
+                        Ok(AsyncSink::NotReady(path)) => {
+                            // The group's channel is full; the sender wakes this task up once
+                            // there's room.
+                            self.pending = Some(path);
+                            return Ok(Async::NotReady);
+                        }
+                        // The group's stream was dropped, so the rest of it isn't wanted.
+                        Err(_) => {}
+                    }
+                    continue;
+                }
+            }
+
+            // This path starts a new group, which also ends the previous one. The path is sent
+            // to the new group's stream on the next poll.
+            let top = path.elements[0].clone();
+            let (sender, receiver) = mpsc::channel(GROUP_BUFFER_SIZE);
+            self.current = Some((top.clone(), sender));
+            self.pending = Some(path);
+            let group: Box<Stream<Item = MPath, Error = S::Error> + Send> =
+                Box::new(receiver.map_err(|()| -> S::Error {
+                    unreachable!("mpsc::Receiver never fails")
+                }));
+            return Ok(Async::Ready(Some((top, group))));
+        }
+    }
+}
+
+/// Sort paths shallowest first, and in `MPath` order within each depth. This guarantees that a
+/// directory comes before everything in it, and that all the paths at one depth come before any
+/// path at the next, so trees can be built up a level at a time.
+pub fn sort_paths_by_depth(paths: &mut Vec<MPath>) {
+    paths.sort_by(|a, b| a.depth().cmp(&b.depth()).then_with(|| a.cmp(b)));
+}
+
+/// The deepest directory containing all of `paths`, which should be file paths. This is
+/// `common_prefix` folded over them, except that when every path is the same file, its parent
+/// directory is returned. `None` if `paths` is empty or has no common first component.
+pub fn deepest_common_dir(paths: &[MPath]) -> Option<MPath> {
+    let (first, rest) = paths.split_first()?;
+    let mut prefix = first.clone();
+    for path in rest {
+        prefix = prefix.common_prefix(path)?;
+    }
+    if paths.iter().all(|path| *path == prefix) {
+        // The prefix is a file, not a directory.
+        prefix.parent()
+    } else {
+        Some(prefix)
+    }
+}
+
+/// Sum the sizes of a flat list of files into every directory that contains them, as for a
+/// `du`-style report. The result is sorted by directory, and includes every directory that is
+/// implied by the file paths, down to the top-level directories. There is no entry for the
+/// repo root, since it has no `MPath`; its total is the sum of all the file sizes.
+pub fn aggregate_dir_sizes(files: &[(MPath, u64)]) -> Vec<(MPath, u64)> {
+    let mut totals: BTreeMap<MPath, u64> = BTreeMap::new();
+    for &(ref path, size) in files {
+        // Every proper prefix of a file's path is a directory containing it.
+        for depth in 1..path.num_components() {
+            let dir = MPath::from_elements(path.elements[..depth].iter());
+            *totals.entry(dir).or_insert(0) += size;
+        }
+    }
+    totals.into_iter().collect()
+}
+
+impl IntoIterator for MPath {
+    type Item = MPathElement;
+    type IntoIter = ::std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MPath {
+    type Item = &'a MPathElement;
+    type IntoIter = Iter<'a, MPathElement>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MPathElement {
+    type Item = &'a MPathElement;
+    type IntoIter = Once<&'a MPathElement>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        once(self)
+    }
+}
+
+impl<'a> From<&'a MPath> for Vec<u8> {
+    fn from(path: &MPath) -> Self {
+        path.to_vec()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for MPath {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        MPath::new(value)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for MPath {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        MPath::new(value.as_bytes())
+    }
+}
+
+impl FromStr for MPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        MPath::new(s.as_bytes())
+    }
+}
+
+lazy_static! {
+    static ref COMPONENT_CHARS: Vec<u8> = (2..b'\n')
+        .chain((b'\n' + 1)..b'/')
+        .chain((b'/' + 1)..255)
+        .collect();
+}
+
+impl Arbitrary for MPathElement {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let size = cmp::max(g.size(), 1);
+        let mut element = Vec::with_capacity(size);
+        for _ in 0..size {
+            let c = g.choose(&COMPONENT_CHARS[..]).unwrap();
+            element.push(*c);
+        }
+        MPathElement(element)
+    }
+}
+
+impl Arbitrary for RepoPath {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        match g.gen_range(0, 3) {
+            0 => RepoPath::RootPath,
+            1 => RepoPath::DirectoryPath(MPath::arbitrary(g)),
+            _ => RepoPath::FilePath(MPath::arbitrary(g)),
+        }
+    }
+}
+
+impl Arbitrary for MPath {
+    #[inline]
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let size = g.size();
+        // Up to sqrt(size) components, each with length from 1 to 2 *
+        // sqrt(size) -- don't generate zero-length components. (This isn't
+        // verified by MPath::verify() but is good to have as a real distribution
+        // of paths.)
+        //
+        // TODO: deal with or filter out '..' and friends.
+        //
+        // TODO: do we really want a uniform distribution over component chars
+        // here?
+        //
+        // TODO: this can generate zero-length paths. Consider having separate
+        // types for possibly-zero-length and non-zero-length paths.
+        let size_sqrt = cmp::max((size as f64).sqrt() as usize, 2);
+
+        let mut path = Vec::new();
+
+        for i in 0..g.gen_range(1, size_sqrt) {
+            if i > 0 {
+                path.push(b'/');
+            }
+            path.extend(
+                (0..g.gen_range(1, 2 * size_sqrt)).map(|_| g.choose(&COMPONENT_CHARS[..]).unwrap()),
+            );
+        }
+
+        MPath::new(path).unwrap()
+    }
+}
+
+impl Display for MPath {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", String::from_utf8_lossy(&self.to_vec()))
+    }
+}
+
+// Implement our own Debug so that strings are displayed properly
+impl fmt::Debug for MPathElement {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "MPathElement({:?} \"{}\")",
+            self.0,
+            String::from_utf8_lossy(&self.0)
+        )
+    }
+}
+
+impl fmt::Debug for MPath {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "MPath({:?} \"{}\")", self.to_vec(), self)
+    }
+}
+
+// The compact serialized form of MPath. This has the same layout that deriving Serialize and
+// Deserialize on MPath itself would produce, so the bincode serialization of MPath is unchanged.
+#[derive(Serialize)]
+#[serde(rename = "MPath")]
+struct CompactMPathRef<'a> {
+    elements: &'a Vec<MPathElement>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "MPath")]
+struct CompactMPath {
+    elements: Vec<MPathElement>,
+}
+
+// The human-readable serialized form of MPath.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum HumanReadableMPath {
+    Utf8(String),
+    Base64 { base64: String },
+}
+
+impl Serialize for MPath {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            let human_readable = match String::from_utf8(self.to_vec()) {
+                Ok(path) => HumanReadableMPath::Utf8(path),
+                Err(err) => HumanReadableMPath::Base64 {
+                    base64: base64::encode(err.as_bytes()),
+                },
+            };
+            human_readable.serialize(serializer)
+        } else {
+            CompactMPathRef {
+                elements: &self.elements,
+            }.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MPath {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let path = match HumanReadableMPath::deserialize(deserializer)? {
+                HumanReadableMPath::Utf8(path) => path.into_bytes(),
+                HumanReadableMPath::Base64 { base64 } => {
+                    base64::decode(&base64).map_err(D::Error::custom)?
+                }
+            };
+            MPath::new(path).map_err(D::Error::custom)
+        } else {
+            let CompactMPath { elements } = CompactMPath::deserialize(deserializer)?;
+            Ok(MPath { elements })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quickcheck::TestResult;
+
+    use super::*;
+
+    quickcheck! {
+        /// Verify that instances generated by quickcheck are valid.
+        fn path_gen(p: MPath) -> bool {
+            let result = MPath::verify(&p.to_vec()).is_ok();
+            result && p.elements
+                .iter()
+                .map(|elem| MPathElement::verify(&elem.as_bytes()))
+                .all(|res| res.is_ok())
+        }
+
+        /// Verify that MPathElement instances generated by quickcheck are valid.
+        fn pathelement_gen(p: MPathElement) -> bool {
+            MPathElement::verify(p.as_bytes()).is_ok()
+        }
+
+        fn elements_to_path(elements: Vec<MPathElement>) -> TestResult {
+            if elements.is_empty() {
+                return TestResult::discard();
+            }
+
+            let joined = elements.iter().map(|elem| elem.0.clone())
+                .collect::<Vec<Vec<u8>>>()
+                .join(&b'/');
+            let expected_len = joined.len();
+            let path = MPath::new(joined).unwrap();
+            TestResult::from_bool(elements == path.elements && path.to_vec().len() == expected_len)
+        }
+
+        fn from_elements_checked(p: MPath) -> bool {
+            MPath::from_elements_checked(p.clone()).expect("path has elements") == p
+        }
+
+        fn path_len(p: MPath) -> bool {
+            p.len() == p.to_vec().len()
+        }
+
+        fn repo_path_stable_bytes_roundtrip(p: RepoPath) -> bool {
+            RepoPath::from_stable_bytes(&p.to_stable_bytes()).expect("invalid stable bytes") == p
+        }
+
+        fn repo_path_serialized_len(p: RepoPath) -> bool {
+            p.serialized_len() == p.serialize().len()
+        }
+
+        fn path_thrift_roundtrip(p: MPath) -> bool {
+            let thrift_path = p.clone().into_thrift();
+            let p2 = MPath::from_thrift(thrift_path)
+                .expect("converting a valid Thrift structure should always work");
+            p == p2
+        }
+
+        fn reverse_components(p: MPath) -> bool {
+            let mut forward: Vec<_> = (&p).into_iter().collect();
+            forward.reverse();
+            p.reverse_components().collect::<Vec<_>>() == forward
+        }
+
+        fn flat_name_roundtrip(p: MPath) -> bool {
+            MPath::from_flat_name(&p.to_flat_name()).expect("invalid flat name") == p
+        }
+
+        fn flat_name_escape_roundtrip(elements: Vec<MPathElement>) -> TestResult {
+            if elements.is_empty() {
+                return TestResult::discard();
+            }
+            // Make sure the escape character and things that look like escapes show up.
+            let elements: Vec<_> = elements
+                .into_iter()
+                .map(|mut elem| {
+                    elem.extend(b"%2F%25%");
+                    elem
+                })
+                .collect();
+            let p = MPath { elements };
+            let flat = p.to_flat_name();
+            TestResult::from_bool(
+                !flat.as_bytes().contains(&b'/')
+                    && MPath::from_flat_name(&flat).expect("invalid flat name") == p
+            )
+        }
+
+        fn parent_roundtrip(p: MPath) -> bool {
+            match p.parent() {
+                Some(parent) => parent.join_element(Some(p.basename())) == p,
+                None => p.num_components() == 1,
+            }
+        }
+
+        fn pathelement_thrift_roundtrip(p: MPathElement) -> bool {
+            let thrift_pathelement = p.clone().into_thrift();
+            let p2 = MPathElement::from_thrift(thrift_pathelement)
+                .expect("converting a valid Thrift structure should always works");
+            p == p2
+        }
+    }
+
+    #[test]
+    fn path_make() {
+        let path = MPath::new(b"1234abc");
+        assert!(MPath::new(b"1234abc").is_ok());
+        assert_eq!(path.unwrap().to_vec().len(), 7);
+    }
+
+    #[test]
+    fn repo_path_make() {
+        let path = MPath::new(b"abc").unwrap();
+        assert_eq!(
+            RepoPath::dir(path.clone()).unwrap(),
+            RepoPath::dir("abc").unwrap()
+        );
+        assert_ne!(RepoPath::dir(path).unwrap(), RepoPath::file("abc").unwrap());
+    }
+
+    #[test]
+    fn repo_path_opt() {
+        assert_eq!(RepoPath::ROOT, RepoPath::root());
+        assert_eq!(RepoPath::file_opt(None), RepoPath::ROOT);
+        assert_eq!(RepoPath::dir_opt(None), RepoPath::ROOT);
+        assert_eq!(RepoPath::ROOT.into_mpath(), None);
+
+        let path = MPath::new("foo/bar").unwrap();
+        assert_eq!(
+            RepoPath::file_opt(Some(path.clone())),
+            RepoPath::FilePath(path.clone())
+        );
+        assert_eq!(
+            RepoPath::dir_opt(Some(path.clone())),
+            RepoPath::DirectoryPath(path.clone())
+        );
+        assert_eq!(
+            RepoPath::file_opt(Some(path.clone())).into_mpath(),
+            Some(path.clone())
+        );
+        assert_eq!(
+            RepoPath::dir_opt(Some(path.clone())).into_mpath(),
+            Some(path)
+        );
+    }
+
+    #[test]
+    fn repo_path_parent() {
+        assert_eq!(RepoPath::root().parent(), None);
+        assert_eq!(
+            RepoPath::file("foo").unwrap().parent(),
+            Some(RepoPath::RootPath)
+        );
+        assert_eq!(
+            RepoPath::dir("foo").unwrap().parent(),
+            Some(RepoPath::RootPath)
+        );
+        assert_eq!(
+            RepoPath::file("foo/bar/baz").unwrap().parent(),
+            Some(RepoPath::dir("foo/bar").unwrap())
+        );
+        assert_eq!(
+            RepoPath::dir("foo/bar").unwrap().parent(),
+            Some(RepoPath::dir("foo").unwrap())
+        );
+    }
+
+    #[test]
+    fn repo_path_stable_bytes() {
+        assert_eq!(RepoPath::root().to_stable_bytes(), b"\x00");
+        assert_eq!(
+            RepoPath::dir("foo/bar").unwrap().to_stable_bytes(),
+            b"\x01foo/bar"
+        );
+        assert_eq!(
+            RepoPath::file("foo/bar").unwrap().to_stable_bytes(),
+            b"\x02foo/bar"
+        );
+
+        RepoPath::from_stable_bytes(b"").expect_err("unexpected OK - empty input");
+        RepoPath::from_stable_bytes(b"\x00foo").expect_err("unexpected OK - root with a path");
+        RepoPath::from_stable_bytes(b"\x01").expect_err("unexpected OK - empty directory path");
+        RepoPath::from_stable_bytes(b"\x03foo").expect_err("unexpected OK - unknown tag");
+    }
+
+    #[test]
+    fn from_elements_checked_empty() {
+        MPath::from_elements_checked(vec![]).expect_err("unexpected OK - no elements");
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("a/b".parse::<MPath>().unwrap(), MPath::new("a/b").unwrap());
+        "".parse::<MPath>().expect_err("unexpected OK - path is empty");
+    }
+
+    #[test]
+    fn empty_paths() {
+        fn assert_empty(path: &str) {
+            MPath::new(path).expect_err(&format!(
+                "unexpected OK - path '{}' is logically empty",
+                path,
+            ));
+        }
+        assert_empty("");
+        assert_empty("/");
+        assert_empty("//");
+        assert_empty("///");
+        assert_empty("////");
+    }
+
+    #[test]
+    fn components() {
+        let foo = MPath::new("foo").unwrap();
+        let foo_bar1 = MPath::new("foo/bar1").unwrap();
+        let foo_bar12 = MPath::new("foo/bar12").unwrap();
+        let baz = MPath::new("baz").unwrap();
+
+        assert_eq!(foo.common_components(&foo), 1);
+        assert_eq!(foo.common_components(&foo_bar1), 1);
+        assert_eq!(foo.common_components(&foo_bar12), 1);
+        assert_eq!(foo_bar1.common_components(&foo_bar1), 2);
+        assert_eq!(foo.common_components(&baz), 0);
+        assert_eq!(foo.common_components(MPath::iter_opt(None)), 0);
+
+        assert_eq!(foo_bar1.take_prefix_components(0).unwrap(), None);
+        assert_eq!(
+            foo_bar1.take_prefix_components(1).unwrap(),
+            Some(foo.clone())
+        );
+        assert_eq!(
+            foo_bar1.take_prefix_components(2).unwrap(),
+            Some(foo_bar1.clone())
+        );
+        foo_bar1
+            .take_prefix_components(3)
+            .expect_err("unexpected OK - too many components");
+    }
+
+    #[test]
+    fn enumerate_components() {
+        let path = MPath::new("a/b/c").unwrap();
+        let a = MPathElement::new(b"a".to_vec()).unwrap();
+        let b = MPathElement::new(b"b".to_vec()).unwrap();
+        let c = MPathElement::new(b"c".to_vec()).unwrap();
+
+        assert_eq!(
+            path.enumerate_components().collect::<Vec<_>>(),
+            vec![(0, &a), (1, &b), (2, &c)]
+        );
+    }
+
+    #[test]
+    fn prefix_successor() {
+        let prefix = MPath::new("foo").unwrap();
+        let successor = prefix.prefix_successor();
+        assert_eq!(successor, MPath::new("foo0").unwrap());
+
+        let start = b"foo/".to_vec();
+        let end = successor.to_vec();
+        let in_range = |path: &str| {
+            let key = MPath::new(path).unwrap().to_vec();
+            start <= key && key < end
+        };
+        assert!(in_range("foo/bar"));
+        assert!(in_range("foo/zzz"));
+        assert!(in_range("foo/\u{ff}"));
+        assert!(!in_range("foo0"));
+        assert!(!in_range("foo.txt"));
+        assert!(!in_range("fop"));
+        assert!(!in_range("foo"));
+
+        // Successors exist even when the last component can't be incremented.
+        let high = MPath::new(b"a/\xff\xff".to_vec()).unwrap();
+        assert_eq!(high.prefix_successor().to_vec(), b"a/\xff\xff0".to_vec());
+    }
+
+    #[test]
+    fn ancestors() {
+        let path = MPath::new("foo/bar/baz").unwrap();
+        assert_eq!(
+            path.ancestors().collect::<Vec<_>>(),
+            paths(vec!["foo/bar/baz", "foo/bar", "foo"])
+        );
+        let path = MPath::new("foo").unwrap();
+        assert_eq!(path.ancestors().collect::<Vec<_>>(), paths(vec!["foo"]));
+    }
+
+    #[test]
+    fn serde_human_readable() {
+        use serde_json;
+
+        let path = MPath::new("foo/bar.txt").unwrap();
+        let json = serde_json::to_string(&path).unwrap();
+        assert_eq!(json, r#""foo/bar.txt""#);
+        assert_eq!(serde_json::from_str::<MPath>(&json).unwrap(), path);
+
+        let path = MPath::new(b"foo/\xff\xfe".to_vec()).unwrap();
+        let json = serde_json::to_string(&path).unwrap();
+        assert_eq!(json, r#"{"base64":"Zm9vL//+"}"#);
+        assert_eq!(serde_json::from_str::<MPath>(&json).unwrap(), path);
+
+        serde_json::from_str::<MPath>(r#""""#).expect_err("unexpected OK - empty path");
+        serde_json::from_str::<MPath>(r#""foo\u0000""#).expect_err("unexpected OK - NUL byte");
+    }
+
+    #[test]
+    fn serde_compact() {
+        // The compact form must stay the same as the derived one.
+        let path = MPath::new("ab/c").unwrap();
+        let mut expected = vec![];
+        expected.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"ab");
+        expected.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"c");
+        let encoded = bincode::serialize(&path).unwrap();
+        assert_eq!(encoded, expected);
+        assert_eq!(bincode::deserialize::<MPath>(&encoded).unwrap(), path);
+    }
+
+    #[test]
+    fn element_from_static() {
+        assert_eq!(
+            MPathElement::from_static("src"),
+            MPathElement::new("src").unwrap()
+        );
+        assert_eq!(DOT.as_bytes(), b".");
+        assert_eq!(DOTDOT.as_bytes(), b"..");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invalid static path element")]
+    fn element_from_static_invalid() {
+        MPathElement::from_static("a/b");
+    }
+
+    #[test]
+    fn element_new() {
+        let expected = MPathElement::new(b"src".to_vec()).unwrap();
+        assert_eq!(MPathElement::new("src").unwrap(), expected);
+        assert_eq!(MPathElement::new(&b"src"[..]).unwrap(), expected);
+        assert_eq!(MPathElement::new(String::from("src")).unwrap(), expected);
+        MPathElement::new("a/b").expect_err("unexpected OK - contains '/'");
+    }
+
+    #[test]
+    fn flat_name() {
+        let path = MPath::new("a%b/c").unwrap();
+        assert_eq!(path.to_flat_name().as_bytes(), b"a%25b%2Fc");
+        assert_eq!(MPath::from_flat_name(&path.to_flat_name()).unwrap(), path);
+
+        let bad = |flat: &str| MPath::from_flat_name(&MPathElement::new(flat).unwrap());
+        bad("a%").expect_err("unexpected OK - truncated escape");
+        bad("a%2").expect_err("unexpected OK - truncated escape");
+        bad("a%41").expect_err("unexpected OK - unknown escape");
+        bad("%2Fa").expect_err("unexpected OK - empty component");
+        bad("a%2F").expect_err("unexpected OK - empty component");
+    }
+
+    #[test]
+    fn normalized() {
+        let normalized = |p: &str| MPath::new_normalized(p).unwrap();
+        assert_eq!(normalized("a/./b"), MPath::new("a/b").unwrap());
+        assert_eq!(normalized("a/b/../c"), MPath::new("a/c").unwrap());
+        assert_eq!(normalized("./a/b/c/../../d/."), MPath::new("a/d").unwrap());
+        assert_eq!(normalized("a/..b/.c"), MPath::new("a/..b/.c").unwrap());
+
+        MPath::new_normalized("../x").expect_err("unexpected OK - '..' above the root");
+        MPath::new_normalized("a/../../x").expect_err("unexpected OK - '..' above the root");
+        MPath::new_normalized("a/..").expect_err("unexpected OK - empty path");
+        MPath::new_normalized("a/\0").expect_err("unexpected OK - contains '\\0'");
+    }
+
+    #[test]
+    fn starts_ends_with() {
+        let path = |p| MPath::new(p).unwrap();
+        let foo_bar_baz = path("foo/bar/baz");
+
+        assert!(foo_bar_baz.starts_with(&path("foo")));
+        assert!(foo_bar_baz.starts_with(&path("foo/bar")));
+        assert!(foo_bar_baz.starts_with(&foo_bar_baz));
+        assert!(!foo_bar_baz.starts_with(&path("fo")));
+        assert!(!foo_bar_baz.starts_with(&path("bar")));
+        assert!(!foo_bar_baz.starts_with(&path("foo/bar/baz/qux")));
+
+        assert!(foo_bar_baz.ends_with(&path("baz")));
+        assert!(foo_bar_baz.ends_with(&path("bar/baz")));
+        assert!(foo_bar_baz.ends_with(&foo_bar_baz));
+        assert!(!foo_bar_baz.ends_with(&path("az")));
+        assert!(!foo_bar_baz.ends_with(&path("foo/bar")));
+        assert!(!foo_bar_baz.ends_with(&path("qux/foo/bar/baz")));
+        assert!(path("tests/fixtures").ends_with(&path("tests/fixtures")));
+        assert!(path("a/tests/fixtures").ends_with(&path("tests/fixtures")));
+        assert!(!path("a/tests/fixtures/b").ends_with(&path("tests/fixtures")));
+    }
+
+    #[test]
+    fn normalize() {
+        let path = MPath::new("foo/bar/baz").unwrap();
+        match path.normalize().unwrap() {
+            Cow::Borrowed(normalized) => assert!(::std::ptr::eq(normalized, &path)),
+            Cow::Owned(_) => panic!("unexpected allocation for an already normal path"),
+        }
+
+        let path = MPath::new("foo/./bar/../baz").unwrap();
+        match path.normalize().unwrap() {
+            Cow::Owned(normalized) => assert_eq!(normalized, MPath::new("foo/baz").unwrap()),
+            Cow::Borrowed(_) => panic!("path should have been normalized"),
+        }
+        assert_eq!(
+            *path.normalize().unwrap(),
+            MPath::new_normalized("foo/./bar/../baz").unwrap()
+        );
+
+        MPath::new("foo/../..")
+            .unwrap()
+            .normalize()
+            .expect_err("unexpected OK - goes above the root");
+        MPath::new("foo/..")
+            .unwrap()
+            .normalize()
+            .expect_err("unexpected OK - normalizes to empty");
+    }
+
+    #[test]
+    fn common_prefix() {
+        let path = |p| MPath::new(p).unwrap();
+        assert_eq!(
+            path("foo/bar/x").common_prefix(&path("foo/bar/y")),
+            Some(path("foo/bar"))
+        );
+        assert_eq!(
+            path("foo/bar").common_prefix(&path("foo/bar/y")),
+            Some(path("foo/bar"))
+        );
+        assert_eq!(path("foo").common_prefix(&path("baz")), None);
+        assert_eq!(path("foo").common_prefix(&path("foo1")), None);
+    }
+
+    #[test]
+    fn common_dir_of_files() {
+        let paths = |ps: &[&str]| -> Vec<MPath> {
+            ps.iter().map(|p| MPath::new(p).unwrap()).collect()
+        };
+        assert_eq!(
+            deepest_common_dir(&paths(&["src/a/x.rs", "src/a/y.rs", "src/a/b/z.rs"])),
+            Some(MPath::new("src/a").unwrap())
+        );
+        assert_eq!(
+            deepest_common_dir(&paths(&["src/a/x.rs", "src/b/y.rs"])),
+            Some(MPath::new("src").unwrap())
+        );
+        assert_eq!(
+            deepest_common_dir(&paths(&["src/a/x.rs", "src/a/x.rs"])),
+            Some(MPath::new("src/a").unwrap())
+        );
+        assert_eq!(deepest_common_dir(&paths(&["src/a/x.rs", "docs/y.md"])), None);
+        assert_eq!(deepest_common_dir(&paths(&["README"])), None);
+        assert_eq!(deepest_common_dir(&[]), None);
+    }
+
+    #[test]
+    fn eq_with_index() {
+        let path = |p| MPath::new(p).unwrap();
+        let index = [MPathElement::new("index.html").unwrap()];
+
+        assert!(path("foo").eq_with_index(&path("foo/index.html"), &index));
+        assert!(path("foo/index.html").eq_with_index(&path("foo"), &index));
+        assert!(path("foo").eq_with_index(&path("foo"), &index));
+        assert!(!path("foo").eq_with_index(&path("foo/index.html"), &[]));
+        assert!(!path("foo").eq_with_index(&path("foo/bar"), &index));
+        assert!(!path("foo/bar").eq_with_index(&path("foo/index.html"), &index));
+        assert!(!path("foo").eq_with_index(&path("foo/bar/index.html"), &index));
+        assert_ne!(path("foo"), path("foo/index.html"));
+    }
+
+    #[test]
+    fn length_prefixed() {
+        let path = MPath::new("foo/bar").unwrap();
+        let mut buf = vec![];
+        path.write_length_prefixed(&mut buf).unwrap();
+        assert_eq!(buf, b"\0\0\0\x07foo/bar");
+        assert_eq!(
+            MPath::read_length_prefixed(&mut buf.as_slice()).unwrap(),
+            path
+        );
+
+        MPath::read_length_prefixed(&mut &b"\0\0\0\x08foo/bar"[..])
+            .expect_err("unexpected OK - length prefix overstates the content");
+        MPath::read_length_prefixed(&mut &b"\0\0\x01\0foo/bar"[..])
+            .expect_err("unexpected OK - length prefix overstates the content");
+        MPath::read_length_prefixed(&mut &b"\0\0\0\x08foo//bar"[..])
+            .expect_err("unexpected OK - path is shorter than its length prefix");
+        MPath::read_length_prefixed(&mut &b"\0\0"[..])
+            .expect_err("unexpected OK - truncated length prefix");
+
+        // Trailing bytes after the frame are left for the caller.
+        let mut input = &b"\0\0\0\x03foobar"[..];
+        assert_eq!(
+            MPath::read_length_prefixed(&mut input).unwrap(),
+            MPath::new("foo").unwrap()
+        );
+        assert_eq!(input, b"bar");
+    }
+
+    #[test]
+    fn builder() {
+        let mut builder = MPathBuilder::new();
+        builder.push(MPathElement::new(b"foo".to_vec()).unwrap());
+        builder.push(MPathElement::new(b"bar".to_vec()).unwrap());
+        assert_eq!(builder.build().unwrap(), MPath::new("foo/bar").unwrap());
+
+        MPathBuilder::new()
+            .build()
+            .expect_err("unexpected OK - no elements pushed");
+    }
+
+    #[test]
+    fn extension() {
+        let path = MPath::new("dir.d/foo.tar.gz").unwrap();
+        assert_eq!(path.extension(), Some(&b"gz"[..]));
+        assert_eq!(path.file_stem(), b"foo.tar");
+
+        let path = MPath::new("dir/.gitignore").unwrap();
+        assert_eq!(path.extension(), None);
+        assert_eq!(path.file_stem(), b".gitignore");
+
+        let path = MPath::new("dir.d/Makefile").unwrap();
+        assert_eq!(path.extension(), None);
+        assert_eq!(path.file_stem(), b"Makefile");
+
+        let path = MPath::new("foo.").unwrap();
+        assert_eq!(path.extension(), Some(&b""[..]));
+        assert_eq!(path.file_stem(), b"foo");
+    }
+
+    #[test]
+    fn no_aliasing() {
+        // Equal elements must still be backed by distinct buffers.
+        let path = MPath::new("foo/foo/foo").unwrap();
+        path.assert_no_aliasing();
+        path.join(&MPath::new("foo").unwrap()).assert_no_aliasing();
+        path.clone().assert_no_aliasing();
+    }
+
+    #[test]
+    fn strip_prefix() {
+        let path = MPath::new("foo/bar/baz").unwrap();
+        assert_eq!(
+            path.strip_prefix(&MPath::new("foo").unwrap()),
+            Some(MPath::new("bar/baz").unwrap())
+        );
+        assert_eq!(
+            path.strip_prefix(&MPath::new("foo/bar").unwrap()),
+            Some(MPath::new("baz").unwrap())
+        );
+        assert_eq!(path.strip_prefix(&path), None);
+        assert_eq!(path.strip_prefix(&MPath::new("fo").unwrap()), None);
+        assert_eq!(path.strip_prefix(&MPath::new("bar").unwrap()), None);
+        assert_eq!(
+            path.strip_prefix(&MPath::new("foo/bar/baz/qux").unwrap()),
+            None
+        );
+        // Comparison is on the raw bytes of each element.
+        let path = MPath::new(b"\xff/a".to_vec()).unwrap();
+        assert_eq!(
+            path.strip_prefix(&MPath::new(b"\xff".to_vec()).unwrap()),
+            Some(MPath::new("a").unwrap())
+        );
+        assert_eq!(
+            path.strip_prefix(&MPath::new("\u{ff}").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn windows_safe() {
+        fn is_safe(path: &str) -> bool {
+            MPath::new(path).unwrap().is_windows_safe()
+        }
+
+        assert!(is_safe("foo/bar.txt"));
+        assert!(is_safe("console/nullable"));
+        assert!(is_safe(".hgignore"));
+        assert!(!is_safe("CON"));
+        assert!(!is_safe("dir/con"));
+        assert!(!is_safe("dir/Lpt1.log"));
+        assert!(!is_safe("foo."));
+        assert!(!is_safe("bar /baz"));
+        assert!(!is_safe("what?"));
+    }
+
+    #[test]
+    fn verify_windows() {
+        fn rule(elem: &str) -> String {
+            match MPathElement::verify_windows(elem.as_bytes())
+                .expect_err("unexpected OK - not a valid Windows file name")
+                .downcast::<ErrorKind>()
+            {
+                Ok(ErrorKind::InvalidPath(_, msg)) => msg,
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+
+        MPathElement::verify_windows(b"README.md").expect("unexpected Err - clean file name");
+        MPathElement::verify_windows(b"console").expect("unexpected Err - clean file name");
+        assert_eq!(rule("CON"), "'CON' is a reserved name on Windows");
+        assert_eq!(rule("con.txt"), "'con' is a reserved name on Windows");
+        assert_eq!(rule("trailing."), "Windows file names cannot end with '.'");
+        assert_eq!(rule("trailing "), "Windows file names cannot end with ' '");
+        assert_eq!(rule("a:b"), "Windows file names cannot contain ':'");
+        assert_eq!(rule("a\\b"), "Windows file names cannot contain '\\'");
+    }
+
+    #[test]
+    fn bad_path() {
+        assert!(MPath::new(b"\0").is_err());
+    }
+    #[test]
+    fn bad_path2() {
+        assert!(MPath::new(b"abc\0").is_err());
+    }
+    #[test]
+    fn bad_path3() {
+        assert!(MPath::new(b"ab\0cde").is_err());
+    }
+
+    #[test]
+    fn bad_path_thrift() {
+        let bad_thrift = thrift::MPath(vec![thrift::MPathElement(b"abc\0".to_vec())]);
+        MPath::from_thrift(bad_thrift).expect_err("unexpected OK - embedded null");
+
+        let bad_thrift = thrift::MPath(vec![thrift::MPathElement(b"def/ghi".to_vec())]);
+        MPath::from_thrift(bad_thrift).expect_err("unexpected OK - embedded slash");
+    }
+
+    #[test]
+    fn path_cmp() {
+        let a = MPath::new(b"a").unwrap();
+        let b = MPath::new(b"b").unwrap();
+
+        assert!(a < b);
+        assert!(a == a);
+        assert!(b == b);
+        assert!(a <= a);
+        assert!(a <= b);
+    }
+
+    #[test]
+    fn ppf() {
+        check_pcf(&paths(vec!["foo", "bar"])).expect("unexpected Err - no directories");
+        check_pcf(&paths(vec!["foo", "foo/bar"]))
+            .expect_err("unexpected OK - foo is a prefix of foo/bar");
+        check_pcf(&paths(vec!["foo", "foo1"]))
+            .expect("unexpected Err - foo is not a path prefix of foo1");
+        check_pcf(&vec![]).expect("unexpected Err - empty path list has no prefixes");
+        // '/' is ASCII 0x2f
+        let mut multi_paths = paths(vec!["foo/bar", "foo/bar\x2e", "foo/bar/baz", "foo/bar\x30"]);
+        // The input calls for a *sorted* list -- this is important.
+        multi_paths.sort_unstable();
+        check_pcf(&multi_paths).expect_err("unexpected OK - other paths and prefixes");
+    }
+
+    #[test]
+    fn ppf_case_insensitive() {
+        let mut sorted = paths(vec!["Foo/bar", "foo/baz"]);
+        sorted.sort_unstable();
+        match check_pcf_case_insensitive(&sorted)
+            .expect_err("unexpected OK - Foo and foo differ only by case")
+            .downcast::<ErrorKind>()
+        {
+            Ok(ErrorKind::CaseConflict(ref p1, ref p2)) => {
+                assert_eq!(p1, &sorted[0]);
+                assert_eq!(p2, &sorted[1]);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut sorted = paths(vec!["a/README", "a/b", "a/readme", "c"]);
+        sorted.sort_unstable();
+        check_pcf_case_insensitive(&sorted)
+            .expect_err("unexpected OK - README and readme differ only by case");
+        let mut sorted = paths(vec!["FOO", "foo/bar"]);
+        sorted.sort_unstable();
+        check_pcf_case_insensitive(&sorted)
+            .expect_err("unexpected OK - file FOO and directory foo differ only by case");
+
+        check_pcf_case_insensitive(&paths(vec!["foo", "foo/bar"]))
+            .expect_err("unexpected OK - foo is a prefix of foo/bar");
+        check_pcf_case_insensitive(&paths(vec!["Foo/bar", "Foo/baz", "foo1"]))
+            .expect("unexpected Err - no case conflicts");
+        check_pcf_case_insensitive(&vec![]).expect("unexpected Err - empty path list");
+    }
+
+    #[test]
+    fn partition() {
+        let sorted = paths(vec![
+            "a/1", "a/2", "a/3", "a/4", "b/1", "b/2", "c/1", "c/2", "c/3"
+        ]);
+        let partitions = partition_balanced(&sorted, 3);
+        // The ideal first split would be between a/3 and a/4, but that cuts through a.
+        assert_eq!(
+            partitions,
+            vec![&sorted[0..4], &sorted[4..6], &sorted[6..9]]
+        );
+
+        assert_eq!(partition_balanced(&sorted, 1), vec![&sorted[..]]);
+        assert_eq!(partition_balanced(&sorted, 20).len(), sorted.len());
+        assert!(partition_balanced(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn dedup() {
+        use futures::{stream, Future};
+
+        let input = paths(vec!["a", "a", "a/b", "b", "b", "b", "c/d"]);
+        let deduped = dedup_sorted(stream::iter_ok::<_, ()>(input))
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(deduped, paths(vec!["a", "a/b", "b", "c/d"]));
+    }
+
+    #[test]
+    fn group_by_top_level() {
+        use futures::{stream, Future};
+
+        // The groups are small enough to be buffered, so the groups' streams can be consumed
+        // after the whole input has been read.
+        let input = paths(vec!["a", "a/b", "a/c/d", "b/e", "c", "c/f"]);
+        let groups = super::group_by_top_level(stream::iter_ok::<_, ()>(input))
+            .collect()
+            .wait()
+            .unwrap();
+        let groups: Vec<_> = groups
+            .into_iter()
+            .map(|(top, group)| (top, group.collect().wait().unwrap()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    MPathElement::new("a").unwrap(),
+                    paths(vec!["a", "a/b", "a/c/d"]),
+                ),
+                (MPathElement::new("b").unwrap(), paths(vec!["b/e"])),
+                (MPathElement::new("c").unwrap(), paths(vec!["c", "c/f"])),
+            ]
+        );
+
+        let empty = super::group_by_top_level(stream::iter_ok::<_, ()>(vec![]))
+            .collect()
+            .wait()
+            .unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn group_by_top_level_large_groups() {
+        use futures::{stream, Future};
+        use std::thread;
+
+        // Groups much larger than the buffer have to be consumed while the input is read, here
+        // on a thread per group.
+        let mut input = Vec::new();
+        for top in &["a", "b"] {
+            for i in 0..(GROUP_BUFFER_SIZE * 5) {
+                input.push(MPath::new(format!("{}/{:04}", top, i)).unwrap());
+            }
+        }
+        let mut workers = Vec::new();
+        super::group_by_top_level(stream::iter_ok::<_, ()>(input.clone()))
+            .for_each(|(top, group)| {
+                workers.push(thread::spawn(move || (top, group.collect().wait().unwrap())));
+                Ok(())
+            })
+            .wait()
+            .unwrap();
+        let groups: Vec<_> = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("worker panicked"))
+            .collect();
+
+        assert_eq!(groups.len(), 2);
+        let (a, b) = input.split_at(GROUP_BUFFER_SIZE * 5);
+        assert_eq!(groups[0], (MPathElement::new("a").unwrap(), a.to_vec()));
+        assert_eq!(groups[1], (MPathElement::new("b").unwrap(), b.to_vec()));
+    }
+
+    #[test]
+    fn sort_by_depth() {
+        let mut input = paths(vec!["b/c/d", "a", "z", "b/c", "a/b", "b", "a/a/a", "a/z"]);
+        sort_paths_by_depth(&mut input);
+        assert_eq!(
+            input,
+            paths(vec!["a", "b", "z", "a/b", "a/z", "b/c", "a/a/a", "b/c/d"])
+        );
+        for (idx, path) in input.iter().enumerate() {
+            for parent in path.ancestors().skip(1) {
+                let parent_idx = input.iter().position(|p| *p == parent);
+                assert!(parent_idx.map_or(true, |parent_idx| parent_idx < idx));
+            }
+        }
+    }
+
+    #[test]
+    fn dir_sizes() {
+        let files = vec![
+            (MPath::new("a/b/1").unwrap(), 1),
+            (MPath::new("a/b/2").unwrap(), 2),
+            (MPath::new("a/c/d/3").unwrap(), 4),
+            (MPath::new("a/4").unwrap(), 8),
+            (MPath::new("e/5").unwrap(), 16),
+            (MPath::new("6").unwrap(), 32),
+        ];
+        let dirs = aggregate_dir_sizes(&files);
+        assert_eq!(
+            dirs,
+            vec![
+                (MPath::new("a").unwrap(), 15),
+                (MPath::new("a/b").unwrap(), 3),
+                (MPath::new("a/c").unwrap(), 4),
+                (MPath::new("a/c/d").unwrap(), 4),
+                (MPath::new("e").unwrap(), 16),
+            ]
+        );
+
+        // Each directory's total is the sum of all the files below it.
+        for &(ref dir, total) in &dirs {
+            let expected: u64 = files
+                .iter()
+                .filter(|&&(ref path, _)| dir.is_prefix_of(path) && dir != path)
+                .map(|&(_, size)| size)
+                .sum();
+            assert_eq!(total, expected, "wrong total for {}", dir);
+        }
+    }
+
+    fn paths<I, T>(paths: I) -> Vec<MPath>
+    where
+        I: IntoIterator<Item = T>,
+        MPath: TryFrom<T, Error = Error>,
+    {
+        let res: Result<Vec<_>> = paths.into_iter().map(|path| path.try_into()).collect();
+        res.expect("invalid input path")
+    }
+}
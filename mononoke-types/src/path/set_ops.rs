@@ -0,0 +1,115 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Set operations over sorted lists of paths.
+//!
+//! All of these functions expect their inputs to be sorted (for example, the keys of a
+//! `BTreeMap<MPath, _>`), and produce sorted outputs. They are implemented as merge-joins,
+//! so they run in linear time and don't need to build up any hash sets.
+
+use std::cmp::Ordering;
+
+use super::MPath;
+
+/// Paths that are in `a` but not in `b`.
+pub fn difference(a: &[MPath], b: &[MPath]) -> Vec<MPath> {
+    let mut ret = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() {
+        if j == b.len() {
+            ret.extend_from_slice(&a[i..]);
+            break;
+        }
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => {
+                ret.push(a[i].clone());
+                i += 1;
+            }
+            Ordering::Equal => {
+                i += 1;
+                j += 1;
+            }
+            Ordering::Greater => {
+                j += 1;
+            }
+        }
+    }
+    ret
+}
+
+/// Paths that are in both `a` and `b`.
+pub fn intersection(a: &[MPath], b: &[MPath]) -> Vec<MPath> {
+    let mut ret = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Equal => {
+                ret.push(a[i].clone());
+                i += 1;
+                j += 1;
+            }
+            Ordering::Greater => j += 1,
+        }
+    }
+    ret
+}
+
+/// Paths that are in either `a` or `b`. Paths present in both are only returned once.
+pub fn union(a: &[MPath], b: &[MPath]) -> Vec<MPath> {
+    let mut ret = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => {
+                ret.push(a[i].clone());
+                i += 1;
+            }
+            Ordering::Equal => {
+                ret.push(a[i].clone());
+                i += 1;
+                j += 1;
+            }
+            Ordering::Greater => {
+                ret.push(b[j].clone());
+                j += 1;
+            }
+        }
+    }
+    ret.extend_from_slice(&a[i..]);
+    ret.extend_from_slice(&b[j..]);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<MPath> {
+        paths
+            .iter()
+            .map(|path| MPath::new(path).expect("invalid input path"))
+            .collect()
+    }
+
+    #[test]
+    fn set_ops() {
+        let a = paths(&["a", "b/c", "b/d", "e"]);
+        let b = paths(&["b/c", "b/e", "e", "f"]);
+
+        assert_eq!(difference(&a, &b), paths(&["a", "b/d"]));
+        assert_eq!(difference(&b, &a), paths(&["b/e", "f"]));
+        assert_eq!(intersection(&a, &b), paths(&["b/c", "e"]));
+        assert_eq!(
+            union(&a, &b),
+            paths(&["a", "b/c", "b/d", "b/e", "e", "f"])
+        );
+
+        assert_eq!(difference(&a, &[]), a);
+        assert_eq!(intersection(&a, &[]), vec![]);
+        assert_eq!(union(&[], &b), b);
+    }
+}
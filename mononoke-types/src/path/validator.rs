@@ -0,0 +1,229 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::ascii;
+
+use errors::*;
+
+use super::{MPath, MPathElement};
+
+/// The rules a `PathValidator` checks paths against.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PathPolicy {
+    /// Bytes that may not appear anywhere in a path, in addition to the bytes that `MPath`
+    /// always forbids. `/` is always a separator, so listing it here has no effect.
+    pub forbidden_bytes: Vec<u8>,
+    /// The maximum length of the whole path, in bytes.
+    pub max_path_len: Option<usize>,
+    /// The maximum length of a single path element, in bytes.
+    pub max_element_len: Option<usize>,
+}
+
+impl Default for PathPolicy {
+    /// The same rules that `MPath::new` checks.
+    fn default() -> Self {
+        PathPolicy {
+            forbidden_bytes: vec![],
+            max_path_len: None,
+            max_element_len: None,
+        }
+    }
+}
+
+/// Bytes that are forbidden in every `MPath`, whatever the policy.
+const MPATH_FORBIDDEN_BYTES: &[u8] = b"\0\x01\n";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ByteClass {
+    Allowed,
+    Separator,
+    Forbidden,
+}
+
+/// Validates paths against a fixed `PathPolicy`.
+///
+/// The policy is compiled into a byte-class table once, so that validating a path is a single
+/// pass over its bytes. This is meant for validating large numbers of paths with the same
+/// policy; with the default policy, `validate` accepts exactly the paths that `MPath::new`
+/// accepts.
+pub struct PathValidator {
+    table: [ByteClass; 256],
+    max_path_len: Option<usize>,
+    max_element_len: Option<usize>,
+}
+
+impl PathValidator {
+    pub fn new(policy: PathPolicy) -> Self {
+        let mut table = [ByteClass::Allowed; 256];
+        // A policy can only add restrictions, so paths that MPath forbids are always rejected.
+        for b in MPATH_FORBIDDEN_BYTES.iter().chain(&policy.forbidden_bytes) {
+            table[*b as usize] = ByteClass::Forbidden;
+        }
+        table[b'/' as usize] = ByteClass::Separator;
+        PathValidator {
+            table,
+            max_path_len: policy.max_path_len,
+            max_element_len: policy.max_element_len,
+        }
+    }
+
+    pub fn validate<P: AsRef<[u8]>>(&self, p: P) -> Result<MPath> {
+        let p = p.as_ref();
+        let invalid = |msg: String| {
+            ErrorKind::InvalidPath(String::from_utf8_lossy(p).into_owned(), msg)
+        };
+
+        if let Some(max_path_len) = self.max_path_len {
+            if p.len() > max_path_len {
+                bail_err!(invalid(format!(
+                    "paths cannot be longer than {} bytes",
+                    max_path_len
+                )));
+            }
+        }
+
+        let mut elements = Vec::new();
+        let mut start = 0;
+        for (idx, b) in p.iter().enumerate() {
+            match self.table[*b as usize] {
+                ByteClass::Allowed => continue,
+                ByteClass::Separator => {
+                    self.push_element(&mut elements, &p[start..idx], &invalid)?;
+                    start = idx + 1;
+                }
+                ByteClass::Forbidden => {
+                    let escaped: Vec<u8> = ascii::escape_default(*b).collect();
+                    bail_err!(invalid(format!(
+                        "paths cannot contain '{}'",
+                        String::from_utf8_lossy(&escaped)
+                    )));
+                }
+            }
+        }
+        self.push_element(&mut elements, &p[start..], &invalid)?;
+
+        if elements.is_empty() {
+            bail_err!(invalid("path cannot be empty".into()));
+        }
+        Ok(MPath { elements })
+    }
+
+    fn push_element<F>(
+        &self,
+        elements: &mut Vec<MPathElement>,
+        elem: &[u8],
+        invalid: &F,
+    ) -> Result<()>
+    where
+        F: Fn(String) -> ErrorKind,
+    {
+        // Empty elements come from leading, trailing or repeated separators, which are ignored
+        // just like in MPath::new.
+        if elem.is_empty() {
+            return Ok(());
+        }
+        if let Some(max_element_len) = self.max_element_len {
+            if elem.len() > max_element_len {
+                bail_err!(invalid(format!(
+                    "path elements cannot be longer than {} bytes",
+                    max_element_len
+                )));
+            }
+        }
+        // Every byte has already been checked against the policy.
+        elements.push(MPathElement(elem.to_vec()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn corpus() -> Vec<Vec<u8>> {
+        vec![
+            b"".to_vec(),
+            b"/".to_vec(),
+            b"//".to_vec(),
+            b"a".to_vec(),
+            b"/a/".to_vec(),
+            b"a//b".to_vec(),
+            b"dir/subdir/file.txt".to_vec(),
+            b"a/\0/b".to_vec(),
+            b"a\x01".to_vec(),
+            b"a\nb".to_vec(),
+            b"\xff\xfe/\x80".to_vec(),
+            b"a b/c\td".to_vec(),
+        ]
+    }
+
+    #[test]
+    fn matches_mpath_new() {
+        let validator = PathValidator::new(PathPolicy::default());
+        for p in corpus() {
+            match (validator.validate(&p), MPath::new(&p)) {
+                (Ok(validated), Ok(expected)) => assert_eq!(validated, expected),
+                (Err(_), Err(_)) => (),
+                (validated, expected) => panic!(
+                    "mismatch for {:?}: validator {:?}, MPath::new {:?}",
+                    p, validated, expected
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn many_paths() {
+        // A bulk run with a single validator, the way it is meant to be used.
+        let validator = PathValidator::new(PathPolicy::default());
+        for i in 0..100_000 {
+            let p = format!("dir{}/subdir{}/file{}.rs", i % 100, i % 10, i);
+            let path = validator.validate(&p).expect("valid path rejected");
+            assert_eq!(path.num_components(), 3);
+        }
+    }
+
+    #[test]
+    fn custom_policy() {
+        let validator = PathValidator::new(PathPolicy {
+            forbidden_bytes: vec![b'\\', b':'],
+            max_path_len: Some(12),
+            max_element_len: Some(4),
+        });
+
+        validator.validate("abcd/efgh").expect("valid path rejected");
+        validator
+            .validate("a:b")
+            .expect_err("unexpected OK - forbidden byte");
+        validator
+            .validate("a\\b")
+            .expect_err("unexpected OK - forbidden byte");
+        validator
+            .validate("abcde/f")
+            .expect_err("unexpected OK - element too long");
+        validator
+            .validate("abcd/efgh/ijk")
+            .expect_err("unexpected OK - path too long");
+        // The bytes MPath forbids are forbidden even though the policy doesn't list them.
+        validator
+            .validate("a\nb")
+            .expect_err("unexpected OK - forbidden by MPath");
+        validator
+            .validate("a\0b")
+            .expect_err("unexpected OK - forbidden by MPath");
+    }
+
+    quickcheck! {
+        fn matches_mpath_new_arbitrary(p: Vec<u8>) -> bool {
+            let validator = PathValidator::new(PathPolicy::default());
+            match (validator.validate(&p), MPath::new(&p)) {
+                (Ok(validated), Ok(expected)) => validated == expected,
+                (Err(_), Err(_)) => true,
+                _ => false,
+            }
+        }
+    }
+}
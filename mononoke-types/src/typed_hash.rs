@@ -9,12 +9,14 @@ use std::str::FromStr;
 
 use ascii::{AsciiStr, AsciiString};
 use quickcheck::{empty_shrinker, Arbitrary, Gen};
+use rust_crypto::digest::Digest;
+use rust_crypto::sha2::Sha256;
 
 use blob::BlobstoreValue;
 use bonsai_changeset::BonsaiChangeset;
 use errors::*;
 use file_contents::FileContents;
-use hash::{Blake2, Context};
+use hash::{Blake2, Context, HashPrefix};
 use thrift;
 
 // There is no NULL_HASH for typed hashes. Any places that need a null hash should use an
@@ -32,24 +34,51 @@ pub trait MononokeId: Copy + Send + 'static {
     fn from_data<T: AsRef<[u8]>>(data: T) -> Self;
 }
 
-/// An identifier for a changeset in Mononoke.
-#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
-#[derive(HeapSizeOf)]
-pub struct ChangesetId(Blake2);
-
-/// An identifier for file contents in Mononoke.
-#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
-#[derive(HeapSizeOf)]
-pub struct ContentId(Blake2);
-
-/// Implementations of typed hashes.
+/// Define a typed hash, along with its context type and implementations. Doc comments before
+/// `hash_type` are attached to the typed hash. A Thrift type with the same name must exist.
+/// Typed hashes with a `value_type` implement `MononokeId`; those without one are for values
+/// that can't be stored in a blobstore yet.
 macro_rules! impl_typed_hash {
     {
+        $(#[$attr: meta])*
         hash_type => $typed: ident,
         value_type => $value_type: ident,
         context_type => $typed_context: ident,
         context_key => $key: expr,
     } => {
+        impl_typed_hash! {
+            $(#[$attr])*
+            hash_type => $typed,
+            context_type => $typed_context,
+            context_key => $key,
+        }
+
+        impl MononokeId for $typed {
+            type Value = $value_type;
+
+            #[inline]
+            fn blobstore_key(&self) -> String {
+                $typed::blobstore_key(self)
+            }
+
+            fn from_data<T: AsRef<[u8]>>(data: T) -> Self {
+                let mut context = $typed_context::new();
+                context.update(data);
+                context.finish()
+            }
+        }
+    };
+    {
+        $(#[$attr: meta])*
+        hash_type => $typed: ident,
+        context_type => $typed_context: ident,
+        context_key => $key: expr,
+    } => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+        #[derive(HeapSizeOf)]
+        pub struct $typed(Blake2);
+
         impl $typed {
             pub const fn new(blake2: Blake2) -> Self {
                 $typed(blake2)
@@ -88,6 +117,25 @@ macro_rules! impl_typed_hash {
                 Blake2::from_ascii_str(s).map(Self::new)
             }
 
+            /// Return a key suitable for blobstore use.
+            #[inline]
+            pub fn blobstore_key(&self) -> String {
+                format!(concat!($key, ".blake2.{}"), self.0)
+            }
+
+            /// Recover an ID from its `blobstore_key`. Fails if the key is for a different kind
+            /// of ID.
+            pub fn parse_blobstore_key(key: &str) -> Result<Self> {
+                let prefix = concat!($key, ".blake2.");
+                if !key.starts_with(prefix) {
+                    bail_err!(ErrorKind::InvalidBlobstoreKey(key.into(), stringify!($typed)));
+                }
+                Blake2::from_str(&key[prefix.len()..])
+                    .map(Self::new)
+                    .context(ErrorKind::InvalidBlobstoreKey(key.into(), stringify!($typed)))
+                    .map_err(Error::from)
+            }
+
             pub fn blake2(&self) -> &Blake2 {
                 &self.0
             }
@@ -140,21 +188,6 @@ macro_rules! impl_typed_hash {
             }
         }
 
-        impl MononokeId for $typed {
-            type Value = $value_type;
-
-            #[inline]
-            fn blobstore_key(&self) -> String {
-                format!(concat!($key, ".blake2.{}"), self.0)
-            }
-
-            fn from_data<T: AsRef<[u8]>>(data: T) -> Self {
-                let mut context = $typed_context::new();
-                context.update(data);
-                context.finish()
-            }
-        }
-
         impl AsRef<[u8]> for $typed {
             fn as_ref(&self) -> &[u8] {
                 self.0.as_ref()
@@ -167,6 +200,15 @@ macro_rules! impl_typed_hash {
             }
         }
 
+        impl FromStr for $typed {
+            type Err = Error;
+
+            #[inline]
+            fn from_str(s: &str) -> Result<Self> {
+                Blake2::from_str(s).map(Self::new)
+            }
+        }
+
         impl Arbitrary for $typed {
             fn arbitrary<G: Gen>(g: &mut G) -> Self {
                 $typed(Blake2::arbitrary(g))
@@ -181,6 +223,7 @@ macro_rules! impl_typed_hash {
 }
 
 impl_typed_hash! {
+    /// An identifier for a changeset in Mononoke.
     hash_type => ChangesetId,
     value_type => BonsaiChangeset,
     context_type => ChangesetIdContext,
@@ -188,12 +231,191 @@ impl_typed_hash! {
 }
 
 impl_typed_hash! {
+    /// An identifier for file contents in Mononoke.
     hash_type => ContentId,
     value_type => FileContents,
     context_type => ContentIdContext,
     context_key => "content",
 }
 
+impl_typed_hash! {
+    /// An identifier for a manifest in Mononoke.
+    hash_type => ManifestId,
+    context_type => ManifestIdContext,
+    context_key => "manifest",
+}
+
+impl ContentId {
+    /// Check that `data`, the contents of a blob as stored, hashes to this ID. Fails with
+    /// `ErrorKind::HashMismatch` if it doesn't, which means the blob is corrupt.
+    pub fn verify(&self, data: &[u8]) -> Result<()> {
+        let actual = ContentId::from_data(data);
+        if actual != *self {
+            bail_err!(ErrorKind::HashMismatch {
+                expected: self.0,
+                actual: actual.0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Compute an id for a file with contents `data`, with the given hash algorithm. Like a
+    /// file's `ContentId`, this is a hash of the serialized `FileContents`, not of `data` itself,
+    /// so with `HashAlgo::Blake2` it has the same digest and blobstore key as the id of
+    /// `FileContents::into_blob`.
+    pub fn compute_with(algo: HashAlgo, data: &[u8]) -> TaggedContentId {
+        let blob = FileContents::new_bytes(data.to_vec()).into_blob();
+        let mut digest = [0; 32];
+        match algo {
+            HashAlgo::Blake2 => digest.copy_from_slice(blob.id().blake2().as_ref()),
+            HashAlgo::Sha256 => {
+                let mut context = Sha256::new();
+                context.input(blob.data());
+                context.result(&mut digest);
+            }
+        }
+        TaggedContentId { algo, digest }
+    }
+}
+
+/// A hash algorithm that content ids can be computed with. See `ContentId::compute_with`.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+pub enum HashAlgo {
+    Blake2,
+    Sha256,
+}
+
+impl HashAlgo {
+    /// The tag recorded for this algorithm in a `TaggedContentId`. Tags are persistent, so they
+    /// must never change.
+    pub fn tag(&self) -> u8 {
+        match *self {
+            HashAlgo::Blake2 => 0,
+            HashAlgo::Sha256 => 1,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(HashAlgo::Blake2),
+            1 => Ok(HashAlgo::Sha256),
+            _ => bail_err!(ErrorKind::InvalidTaggedContentId(format!(
+                "unknown hash algorithm tag: {}",
+                tag
+            ))),
+        }
+    }
+
+    /// The name of this algorithm, as used in blobstore keys.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            HashAlgo::Blake2 => "blake2",
+            HashAlgo::Sha256 => "sha256",
+        }
+    }
+}
+
+/// An identifier for file contents that records the hash algorithm it was computed with. Ids
+/// computed with different algorithms are never equal, even if their digests are.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+pub struct TaggedContentId {
+    algo: HashAlgo,
+    digest: [u8; 32],
+}
+
+impl TaggedContentId {
+    /// Parse an id serialized by `to_bytes`: a one-byte algorithm tag followed by the 32-byte
+    /// digest.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 33 {
+            bail_err!(ErrorKind::InvalidTaggedContentId(format!(
+                "need exactly 33 bytes, got {}",
+                bytes.len()
+            )));
+        }
+        let algo = HashAlgo::from_tag(bytes[0])?;
+        let mut digest = [0; 32];
+        digest.copy_from_slice(&bytes[1..]);
+        Ok(TaggedContentId { algo, digest })
+    }
+
+    /// Serialize this id as its algorithm tag followed by its digest.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(33);
+        bytes.push(self.algo.tag());
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    #[inline]
+    pub fn algo(&self) -> HashAlgo {
+        self.algo
+    }
+
+    #[inline]
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// Return a key suitable for blobstore use. For BLAKE2 ids, this is the same as
+    /// `ContentId::blobstore_key`.
+    pub fn blobstore_key(&self) -> String {
+        let hex: String = self.digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("content.{}.{}", self.algo.name(), hex)
+    }
+}
+
+impl From<ContentId> for TaggedContentId {
+    fn from(id: ContentId) -> Self {
+        let mut digest = [0; 32];
+        digest.copy_from_slice(id.blake2().as_ref());
+        TaggedContentId {
+            algo: HashAlgo::Blake2,
+            digest,
+        }
+    }
+}
+
+/// An abbreviated `ChangesetId`, as typed by users.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub struct ChangesetIdPrefix(HashPrefix);
+
+impl ChangesetIdPrefix {
+    pub fn new(prefix: HashPrefix) -> Self {
+        ChangesetIdPrefix(prefix)
+    }
+
+    /// The smallest `ChangesetId` that starts with this prefix.
+    pub fn min_bound(&self) -> ChangesetId {
+        ChangesetId::new(self.0.min_bound())
+    }
+
+    /// The largest `ChangesetId` that starts with this prefix. Every ID in
+    /// `min_bound()..=max_bound()` matches, so a store can resolve the prefix with a range scan.
+    pub fn max_bound(&self) -> ChangesetId {
+        ChangesetId::new(self.0.max_bound())
+    }
+
+    pub fn matches(&self, id: ChangesetId) -> bool {
+        self.0.matches(id.blake2())
+    }
+}
+
+impl FromStr for ChangesetIdPrefix {
+    type Err = Error;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        HashPrefix::from_str(s).map(Self::new)
+    }
+}
+
+impl Display for ChangesetIdPrefix {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -212,6 +434,56 @@ mod test {
                 .expect("converting a valid Thrift structure should always work");
             h == sh
         }
+
+        fn changesetid_full_roundtrip(h: ChangesetId) -> bool {
+            let from_thrift = ChangesetId::from_thrift(h.into_thrift())
+                .expect("converting a valid Thrift structure should always work");
+            let from_str: ChangesetId = h.to_string().parse().expect("hex should parse");
+            let from_hex = ChangesetId::from_ascii_str(&h.to_hex()).expect("hex should parse");
+            let from_bytes = ChangesetId::from_bytes(h.as_ref()).expect("bytes should parse");
+            let from_key = ChangesetId::parse_blobstore_key(&h.blobstore_key())
+                .expect("blobstore key should parse");
+            [from_thrift, from_str, from_hex, from_bytes, from_key].iter().all(|id| *id == h)
+        }
+
+        fn manifestid_thrift_roundtrip(h: ManifestId) -> bool {
+            let v = h.into_thrift();
+            let sh = ManifestId::from_thrift(v)
+                .expect("converting a valid Thrift structure should always work");
+            h == sh
+        }
+
+        fn changesetid_string_roundtrip(h: ChangesetId) -> bool {
+            h.to_string().parse::<ChangesetId>().ok() == Some(h)
+        }
+
+        fn contentid_string_roundtrip(h: ContentId) -> bool {
+            h.to_string().parse::<ContentId>().ok() == Some(h)
+        }
+
+        fn blobstore_key_roundtrip(h: Blake2) -> bool {
+            let changeset_id = ChangesetId::new(h);
+            let content_id = ContentId::new(h);
+            let changeset_key = changeset_id.blobstore_key();
+            let content_key = content_id.blobstore_key();
+            changeset_key != content_key
+                && ChangesetId::parse_blobstore_key(&changeset_key).ok() == Some(changeset_id)
+                && ContentId::parse_blobstore_key(&content_key).ok() == Some(content_id)
+                && ChangesetId::parse_blobstore_key(&content_key).is_err()
+                && ContentId::parse_blobstore_key(&changeset_key).is_err()
+        }
+    }
+
+    #[test]
+    fn parse_bad() {
+        let hex = "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8";
+        hex.parse::<ChangesetId>().expect("valid hex should parse");
+        hex[..63]
+            .parse::<ChangesetId>()
+            .expect_err("unexpected OK - too short");
+        format!("{}0", hex)
+            .parse::<ContentId>()
+            .expect_err("unexpected OK - too long");
     }
 
     #[test]
@@ -223,5 +495,113 @@ mod test {
 
         let id = ContentId::new(Blake2::from_byte_array([1; 32]));
         assert_eq!(id.blobstore_key(), format!("content.blake2.{}", id));
+
+        let id = ManifestId::new(Blake2::from_byte_array([1; 32]));
+        assert_eq!(id.blobstore_key(), format!("manifest.blake2.{}", id));
+    }
+
+    #[test]
+    fn compute_with_blake2() {
+        let blob = FileContents::new_bytes(&b"abc"[..]).into_blob();
+        let id = ContentId::compute_with(HashAlgo::Blake2, b"abc");
+        assert_eq!(id.algo(), HashAlgo::Blake2);
+        assert_eq!(id, TaggedContentId::from(*blob.id()));
+        assert_eq!(id.blobstore_key(), blob.id().blobstore_key());
+        assert_eq!(TaggedContentId::from_bytes(&id.to_bytes()).unwrap(), id);
+    }
+
+    #[test]
+    fn compute_with_sha256() {
+        let blob = FileContents::new_bytes(&b"abc"[..]).into_blob();
+        let id = ContentId::compute_with(HashAlgo::Sha256, b"abc");
+        assert_eq!(id.algo(), HashAlgo::Sha256);
+        let mut expected = [0; 32];
+        let mut context = Sha256::new();
+        context.input(blob.data());
+        context.result(&mut expected);
+        assert_eq!(id.digest(), &expected[..]);
+        assert!(id.blobstore_key().starts_with("content.sha256."));
+        assert_eq!(TaggedContentId::from_bytes(&id.to_bytes()).unwrap(), id);
+    }
+
+    #[test]
+    fn tagged_content_id_cross_algo() {
+        let blake2 = ContentId::compute_with(HashAlgo::Blake2, b"abc");
+        let sha256 = ContentId::compute_with(HashAlgo::Sha256, b"abc");
+        assert_ne!(blake2, sha256);
+
+        // Same digest, different tag.
+        let mut bytes = blake2.to_bytes();
+        bytes[0] = HashAlgo::Sha256.tag();
+        let retagged = TaggedContentId::from_bytes(&bytes).unwrap();
+        assert_eq!(retagged.digest(), blake2.digest());
+        assert_ne!(retagged, blake2);
+    }
+
+    #[test]
+    fn tagged_content_id_bad() {
+        let bytes = ContentId::compute_with(HashAlgo::Sha256, b"abc").to_bytes();
+        TaggedContentId::from_bytes(&bytes[..32]).expect_err("unexpected OK - too short");
+        let mut bytes = bytes;
+        bytes[0] = 0xff;
+        TaggedContentId::from_bytes(&bytes).expect_err("unexpected OK - unknown tag");
+    }
+
+    #[test]
+    fn changeset_id_prefix() {
+        let prefix = ChangesetIdPrefix::from_str("a1").unwrap();
+        let id = |s| ChangesetId::from_str(s).unwrap();
+        assert_eq!(
+            prefix.min_bound(),
+            id("a100000000000000000000000000000000000000000000000000000000000000")
+        );
+        assert_eq!(
+            prefix.max_bound(),
+            id("a1ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+        );
+        assert!(prefix.matches(prefix.min_bound()));
+        assert!(prefix.matches(prefix.max_bound()));
+        assert!(prefix.matches(id(
+            "a15751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+        )));
+        assert!(!prefix.matches(id(
+            "a05751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+        )));
+        assert!(!prefix.matches(id(
+            "a200000000000000000000000000000000000000000000000000000000000000"
+        )));
+        assert_eq!(format!("{}", prefix), "a1");
+        ChangesetIdPrefix::from_str("a1x").expect_err("unexpected OK - badchar");
+    }
+
+    #[test]
+    fn verify() {
+        let mut data = b"foo\nbar\n".to_vec();
+        let id = ContentId::from_data(&data);
+        id.verify(&data).expect("unmodified data should verify");
+
+        let blob = FileContents::new_bytes("foo").into_blob();
+        blob.id().verify(blob.data()).expect("blob should verify");
+
+        data[2] ^= 0x10;
+        let actual = ContentId::from_data(&data);
+        let err = id.verify(&data).expect_err("unexpected OK - data was modified");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::HashMismatch { expected, actual: got })
+                if expected == id.0 && got == actual.0 => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_blobstore_key_bad() {
+        let hex = "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8";
+        ContentId::parse_blobstore_key(&format!("content.blake2.{}", hex))
+            .expect("valid key should parse");
+        ContentId::parse_blobstore_key(hex).expect_err("unexpected OK - no prefix");
+        ContentId::parse_blobstore_key(&format!("content.sha1.{}", hex))
+            .expect_err("unexpected OK - wrong hash type");
+        ContentId::parse_blobstore_key(&format!("content.blake2.{}", &hex[..63]))
+            .expect_err("unexpected OK - truncated hash");
     }
 }
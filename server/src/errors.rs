@@ -16,4 +16,8 @@ pub enum ErrorKind {
     #[fail(display = "connection does not start with preamble")] NoConnectionPreamble,
     #[fail(display = "connection error while reading preamble")] ConnectionError,
     #[fail(display = "incorrect reponame: {}", _0)] IncorrectRepoName(String),
+    #[fail(display = "connection output was dropped before it was flushed")] OutputDropped,
+    #[fail(display = "connection exceeded its quota of {} bytes", _0)] QuotaExceeded(usize),
+    #[fail(display = "user {} already has the maximum of {} connections", _0, _1)]
+    TooManyConnections(String, usize),
 }
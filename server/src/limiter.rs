@@ -0,0 +1,139 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use sshrelay::Preamble;
+
+use errors::*;
+
+/// The preamble `misc` key holding the connecting user's name.
+const USERNAME_KEY: &str = "unix_username";
+
+/// Caps how many connections each user may have open at once. Users are identified by the
+/// `unix_username` in the connection preamble; connections without one all share a single
+/// anonymous bucket.
+#[derive(Clone, Debug)]
+pub struct PerUserLimiter {
+    max_connections: usize,
+    // Keyed by username, with None for anonymous connections.
+    live: Arc<Mutex<HashMap<Option<String>, usize>>>,
+}
+
+impl PerUserLimiter {
+    pub fn new(max_connections: usize) -> Self {
+        PerUserLimiter {
+            max_connections,
+            live: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Count a new connection against its user's limit. Fails with
+    /// `ErrorKind::TooManyConnections` if the user already has the maximum number open.
+    /// Otherwise the connection is counted until the returned guard is dropped, so the guard
+    /// should live as long as the connection does.
+    pub fn acquire(&self, preamble: &Preamble) -> Result<ConnectionGuard> {
+        let user = match preamble.misc.get(USERNAME_KEY) {
+            Some(user) if !user.is_empty() => Some(user.clone()),
+            _ => None,
+        };
+
+        let mut live = self.live.lock().expect("lock poisoned");
+        let count = live.entry(user.clone()).or_insert(0);
+        if *count >= self.max_connections {
+            let user = user.unwrap_or_else(|| "<anonymous>".into());
+            bail_err!(ErrorKind::TooManyConnections(user, self.max_connections));
+        }
+        *count += 1;
+
+        Ok(ConnectionGuard {
+            user,
+            live: self.live.clone(),
+        })
+    }
+
+    /// The number of connections `user` (or anonymous users, for None) has open.
+    #[cfg(test)]
+    pub fn live_connections(&self, user: Option<&str>) -> usize {
+        let live = self.live.lock().expect("lock poisoned");
+        live.get(&user.map(String::from)).cloned().unwrap_or(0)
+    }
+}
+
+/// Keeps a connection counted by a `PerUserLimiter` until dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    user: Option<String>,
+    live: Arc<Mutex<HashMap<Option<String>, usize>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        // Don't panic if the lock is poisoned, since this may be running during unwinding.
+        if let Ok(mut live) = self.live.lock() {
+            let remove = match live.get_mut(&self.user) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                None => false,
+            };
+            if remove {
+                live.remove(&self.user);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn preamble(user: Option<&str>) -> Preamble {
+        let mut preamble = Preamble::new("repo".into());
+        if let Some(user) = user {
+            preamble.misc.insert(USERNAME_KEY.into(), user.into());
+        }
+        preamble
+    }
+
+    #[test]
+    fn limit() {
+        let limiter = PerUserLimiter::new(2);
+        let first = limiter.acquire(&preamble(Some("alice"))).expect("first failed");
+        let _second = limiter.acquire(&preamble(Some("alice"))).expect("second failed");
+        let err = limiter
+            .acquire(&preamble(Some("alice")))
+            .expect_err("unexpected OK - over the limit");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::TooManyConnections(ref user, 2)) if user == "alice" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(limiter.live_connections(Some("alice")), 2);
+
+        // Other users have their own limits.
+        let _bob = limiter.acquire(&preamble(Some("bob"))).expect("bob failed");
+
+        drop(first);
+        assert_eq!(limiter.live_connections(Some("alice")), 1);
+        let _third = limiter.acquire(&preamble(Some("alice"))).expect("third failed");
+    }
+
+    #[test]
+    fn anonymous() {
+        let limiter = PerUserLimiter::new(1);
+        let guard = limiter.acquire(&preamble(None)).expect("first failed");
+        limiter
+            .acquire(&preamble(Some("")))
+            .expect_err("unexpected OK - empty username is anonymous");
+        assert_eq!(limiter.live_connections(None), 1);
+
+        drop(guard);
+        assert_eq!(limiter.live_connections(None), 0);
+        limiter.acquire(&preamble(Some(""))).expect("second failed");
+    }
+}
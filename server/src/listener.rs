@@ -4,18 +4,25 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
-use std::io;
+use std::cmp;
+use std::io::{self, BufReader};
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use failure::Error;
-use futures::{Future, Stream};
-use futures::sync::mpsc;
+use futures::{stream, Future, Stream};
+use futures::sync::{mpsc, oneshot};
 use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 
 use bytes::Bytes;
 use errors::*;
+use net2::TcpBuilder;
+use rand;
 use tokio::net::{TcpListener, TcpStream};
-use tokio_core::reactor::Remote;
+use tokio::reactor::Handle;
+use tokio_core::reactor::{Interval, Remote};
 use tokio_io::{AsyncRead, AsyncWrite, IoStream};
 // TODO: (rain1) T30794235 move mononoke/server to tokio-codec
 #[allow(deprecated)]
@@ -23,56 +30,275 @@ use tokio_io::codec::{FramedRead, FramedWrite};
 
 use sshrelay::{Preamble, SshDecoder, SshEncoder, SshMsg, SshStream};
 
-pub fn listener<P>(sockname: P) -> io::Result<IoStream<TcpStream>>
+/// Socket options applied to the listening socket before it is bound.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListenerConfig {
+    /// Set `SO_REUSEADDR`, so that a restarted server can bind its address while connections
+    /// from the previous process are still in `TIME_WAIT`.
+    pub reuse_address: bool,
+    /// Set `SO_REUSEPORT`, so that several processes can listen on the same address. Ignored on
+    /// platforms other than Unix.
+    pub reuse_port: bool,
+    /// The maximum number of pending connections queued by the kernel.
+    pub backlog: i32,
+}
+
+impl Default for ListenerConfig {
+    /// The same options that `tokio::net::TcpListener::bind` uses.
+    fn default() -> Self {
+        ListenerConfig {
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            backlog: 1024,
+        }
+    }
+}
+
+pub fn listener<P>(sockname: P, config: &ListenerConfig) -> io::Result<IoStream<TcpStream>>
 where
     P: AsRef<str>,
 {
     let sockname = sockname.as_ref();
-    let listener;
     let addr: SocketAddr = sockname.parse().unwrap();
 
-    // First bind the socket. If the socket already exists then try connecting to it;
-    // if there's no connection then replace it with a new one. (This assumes that simply
-    // connecting is a no-op).
-    loop {
-        match TcpListener::bind(&addr) {
-            Ok(l) => {
-                listener = l;
-                break;
-            }
-            Err(err) => {
-                return Err(err);
-            }
+    let listener = bind(&addr, config)?;
+    Ok(listener.incoming().boxify())
+}
+
+fn bind(addr: &SocketAddr, config: &ListenerConfig) -> io::Result<TcpListener> {
+    let builder = match *addr {
+        SocketAddr::V4(..) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(..) => TcpBuilder::new_v6()?,
+    };
+    builder.reuse_address(config.reuse_address)?;
+    set_reuse_port(&builder, config.reuse_port)?;
+    builder.bind(addr)?;
+    let listener = builder.listen(config.backlog)?;
+
+    TcpListener::from_std(listener, &Handle::default())
+}
+
+#[cfg(unix)]
+fn set_reuse_port(builder: &TcpBuilder, reuse_port: bool) -> io::Result<()> {
+    use net2::unix::UnixTcpBuilderExt;
+
+    builder.reuse_port(reuse_port).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_builder: &TcpBuilder, _reuse_port: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// Byte and command counts for a connection, shared by everything that handles it.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceUsage {
+    inner: Arc<ResourceUsageInner>,
+}
+
+#[derive(Debug, Default)]
+struct ResourceUsageInner {
+    bytes_in: AtomicUsize,
+    bytes_out: AtomicUsize,
+    commands: AtomicUsize,
+    byte_quota: Option<usize>,
+}
+
+impl ResourceUsage {
+    pub fn new(byte_quota: Option<usize>) -> Self {
+        ResourceUsage {
+            inner: Arc::new(ResourceUsageInner {
+                byte_quota,
+                ..Default::default()
+            }),
         }
     }
 
-    Ok(listener.incoming().boxify())
+    /// The number of stdin bytes read from the client.
+    pub fn bytes_in(&self) -> usize {
+        self.inner.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// The number of stdout and stderr bytes sent to the client.
+    pub fn bytes_out(&self) -> usize {
+        self.inner.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// The number of commands recorded with `record_command`.
+    pub fn commands(&self) -> usize {
+        self.inner.commands.load(Ordering::Relaxed)
+    }
+
+    pub fn record_command(&self) {
+        self.inner.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The maximum number of bytes that may be transferred in either direction.
+    pub fn byte_quota(&self) -> Option<usize> {
+        self.inner.byte_quota
+    }
+
+    /// Whether more bytes have been transferred than the quota allows.
+    pub fn quota_exceeded(&self) -> bool {
+        match self.inner.byte_quota {
+            Some(quota) => self.bytes_in() + self.bytes_out() > quota,
+            None => false,
+        }
+    }
+
+    /// Add `len` bytes to `counter`. Fails with an I/O error wrapping `ErrorKind::QuotaExceeded`
+    /// if this takes the connection over its quota.
+    fn add_bytes(&self, counter: &AtomicUsize, len: usize) -> io::Result<()> {
+        counter.fetch_add(len, Ordering::Relaxed);
+        if self.quota_exceeded() {
+            let quota = self.byte_quota().unwrap_or_default();
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                Error::from(ErrorKind::QuotaExceeded(quota)).compat(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn add_bytes_in(&self, len: usize) -> io::Result<()> {
+        self.add_bytes(&self.inner.bytes_in, len)
+    }
+
+    fn add_bytes_out(&self, len: usize) -> io::Result<()> {
+        self.add_bytes(&self.inner.bytes_out, len)
+    }
 }
 
 pub struct Stdio {
     pub preamble: Preamble,
+    // Id to trace this connection with: the one supplied by the client if it's valid, or a
+    // generated one.
+    pub trace_id: String,
+    // Bytes transferred over this connection. If the byte quota is exceeded, stdin returns an
+    // error and output to the client stops.
+    pub usage: ResourceUsage,
     pub stdin: BoxStream<Bytes, io::Error>,
     pub stdout: mpsc::Sender<Bytes>,
     pub stderr: mpsc::Sender<Bytes>,
+    // Resolves once everything sent to stdout and stderr has been written out and flushed.
+    output_done: oneshot::Receiver<io::Result<()>>,
+}
+
+impl Stdio {
+    /// Close stdout and stderr, and wait until everything that was sent to them has been
+    /// written to the underlying stream and flushed.
+    ///
+    /// Note that the output is only closed once *all* clones of the stdout and stderr senders
+    /// have been dropped, so any clones must be dropped for the returned future to complete.
+    ///
+    /// If output stopped because the connection's byte quota was exceeded, this fails with
+    /// `ErrorKind::QuotaExceeded`.
+    pub fn close_output(self) -> BoxFuture<(), Error> {
+        let Stdio {
+            stdout,
+            stderr,
+            output_done,
+            usage,
+            ..
+        } = self;
+        drop(stdout);
+        drop(stderr);
+
+        output_done
+            .map_err(|_| Error::from(ErrorKind::OutputDropped))
+            .and_then(move |res| {
+                if usage.quota_exceeded() {
+                    let quota = usage.byte_quota().unwrap_or_default();
+                    return Err(ErrorKind::QuotaExceeded(quota).into());
+                }
+                res.map_err(Error::from)
+            })
+            .boxify()
+    }
+}
+
+/// The default range that keepalive intervals requested by clients are clamped to, in seconds.
+const MIN_KEEPALIVE_SECS: u64 = 5;
+const MAX_KEEPALIVE_SECS: u64 = 300;
+
+/// The keepalive interval to use for a client that asked for `requested_secs`, clamped to the
+/// range in `config`. Keepalives are disabled if the client didn't ask for them, or asked for an
+/// interval of 0.
+fn keepalive_interval(requested_secs: Option<u64>, config: &MuxConfig) -> Option<Duration> {
+    match requested_secs {
+        None | Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(cmp::min(
+            cmp::max(secs, config.min_keepalive_secs),
+            config.max_keepalive_secs,
+        ))),
+    }
+}
+
+/// Generate a random 128-bit trace id, rendered as hex.
+fn generate_trace_id() -> String {
+    format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>())
+}
+
+/// Whether a trace id supplied by a client has the same form as a generated one: exactly 32 hex
+/// digits. Other ids are replaced with a generated one.
+fn is_valid_trace_id(trace_id: &str) -> bool {
+    trace_id.len() == 32 && trace_id.chars().all(|c| c.is_digit(16))
+}
+
+/// Options for `ssh_server_mux`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MuxConfig {
+    /// The minimum number of bytes to ask for in each read from the client stream. Reads are
+    /// buffered, so that large frames can be read with fewer, larger reads. If this is `None`,
+    /// reads are sized by the frame decoder's buffer, which starts small and grows as needed.
+    pub read_buffer_capacity: Option<usize>,
+    /// The maximum number of bytes that may be transferred over the connection, counting both
+    /// stdin and output. See `Stdio::usage`.
+    pub byte_quota: Option<usize>,
+    /// The range that keepalive intervals requested by clients are clamped to, in seconds.
+    pub min_keepalive_secs: u64,
+    pub max_keepalive_secs: u64,
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        MuxConfig {
+            read_buffer_capacity: None,
+            byte_quota: None,
+            min_keepalive_secs: MIN_KEEPALIVE_SECS,
+            max_keepalive_secs: MAX_KEEPALIVE_SECS,
+        }
+    }
 }
 
 // As a server, given a stream to a client, return an Io pair with stdin/stdout, and an
 // auxillary sink for stderr.
-pub fn ssh_server_mux<S>(s: S, remote: Remote) -> BoxFuture<Stdio, Error>
+pub fn ssh_server_mux<S>(
+    s: S,
+    remote: Remote,
+    config: &MuxConfig,
+) -> BoxFuture<Stdio, Error>
 where
     S: AsyncRead + AsyncWrite + Send + 'static,
 {
+    let usage = ResourceUsage::new(config.byte_quota);
     let (rx, tx) = s.split();
+    let rx: Box<AsyncRead + Send> = match config.read_buffer_capacity {
+        Some(capacity) => Box::new(BufReader::with_capacity(capacity, rx)),
+        None => Box::new(rx),
+    };
     // TODO: (rain1) T30794235 move mononoke/server to tokio-codec
     #[allow(deprecated)]
     let wr = FramedWrite::new(tx, SshEncoder::new());
     #[allow(deprecated)]
     let rd = FramedRead::new(rx, SshDecoder::new());
+    let config = config.clone();
 
     rd.into_future()
         .map_err(|_err| ErrorKind::ConnectionError.into())
         .and_then(move |(maybe_preamble, rd)| {
-            let preamble = match maybe_preamble {
+            let mut preamble = match maybe_preamble {
                 Some(maybe_preamble) => {
                     if let SshStream::Preamble(preamble) = maybe_preamble.stream() {
                         preamble
@@ -85,14 +311,27 @@ where
                 }
             };
 
+            let trace_id = match preamble.trace_id {
+                Some(ref trace_id) if is_valid_trace_id(trace_id) => trace_id.clone(),
+                _ => generate_trace_id(),
+            };
+            preamble.trace_id = Some(trace_id.clone());
+            let keepalive = keepalive_interval(preamble.keepalive_secs, &config);
+            preamble.keepalive_secs = Some(keepalive.map_or(0, |interval| interval.as_secs()));
+
             let stdin = rd.filter_map(|s| {
                 if s.stream() == SshStream::Stdin {
                     Some(s.data())
                 } else {
                     None
                 }
-            }).boxify();
+            }).and_then({
+                    let usage = usage.clone();
+                    move |data| usage.add_bytes_in(data.len()).map(|()| data)
+                })
+                .boxify();
 
+            let (output_done_tx, output_done) = oneshot::channel();
             let (stdout, stderr) = {
                 let (otx, orx) = mpsc::channel(1);
                 let (etx, erx) = mpsc::channel(1);
@@ -100,23 +339,423 @@ where
                 let orx = orx.map(|v| SshMsg::new(SshStream::Stdout, v));
                 let erx = erx.map(|v| SshMsg::new(SshStream::Stderr, v));
 
-                // Glue them together
-                let fwd = orx.select(erx)
-                    .map_err(|()| io::Error::new(io::ErrorKind::Other, "huh?"))
-                    .forward(wr);
+                // Glue them together. The output is followed by a `None` marker, so that
+                // the forwarding stops once stdout and stderr are closed even though
+                // keepalives would go on forever.
+                let last_output = Arc::new(Mutex::new(Instant::now()));
+                let output = orx.select(erx)
+                    .inspect({
+                        let last_output = last_output.clone();
+                        move |_| *last_output.lock().expect("lock poisoned") = Instant::now()
+                    })
+                    .map(Some)
+                    .chain(stream::once(Ok(None)))
+                    .map_err(|()| io::Error::new(io::ErrorKind::Other, "huh?"));
+                let usage = usage.clone();
 
-                // spawn a task for forwarding stdout/err into stream
-                remote.spawn(|_handle| fwd.discard());
+                // spawn a task for forwarding stdout/err into stream, and signal once the
+                // stream has been flushed
+                remote.spawn(move |handle| {
+                    let keepalives = match keepalive {
+                        Some(interval) => match Interval::new(interval, handle) {
+                            Ok(ticks) => ticks
+                                .filter(move |_| {
+                                    last_output.lock().expect("lock poisoned").elapsed()
+                                        >= interval
+                                })
+                                .map(|()| Some(SshMsg::new(SshStream::Stderr, Bytes::new())))
+                                .boxify(),
+                            Err(err) => stream::once(Err(err)).boxify(),
+                        },
+                        None => stream::empty().boxify(),
+                    };
+
+                    output
+                        .select(keepalives)
+                        .take_while(|msg| Ok(msg.is_some()))
+                        .filter_map(|msg| msg)
+                        .and_then(move |msg| {
+                            usage.add_bytes_out(msg.as_ref().len()).map(|()| msg)
+                        })
+                        .forward(wr)
+                        .then(move |res| {
+                            let _ = output_done_tx.send(res.map(|_| ()));
+                            Ok::<_, ()>(())
+                        })
+                });
 
                 (otx, etx)
             };
 
             Ok(Stdio {
                 preamble,
+                trace_id,
+                usage,
                 stdin,
                 stdout,
                 stderr,
+                output_done,
             })
         })
         .boxify()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{Cursor, Read, Write};
+
+    use bytes::BytesMut;
+    use failure::Compat;
+    use futures::{Async, Poll, Sink};
+    use tokio_core::reactor::{Core, Timeout};
+    use tokio_io::codec::{Decoder, Encoder};
+
+    /// An in-memory connection: reads come from a fixed buffer, and writes are collected into a
+    /// shared buffer.
+    struct TestConnection {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+        // The number of reads made from this connection.
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Read for TestConnection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for TestConnection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().expect("lock poisoned").write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl TestConnection {
+        /// Queue `data` to be read from the connection as a stdin frame.
+        fn push_stdin(&mut self, data: &str) {
+            let mut input = BytesMut::from(self.input.get_ref().clone());
+            SshEncoder::new()
+                .encode(SshMsg::new(SshStream::Stdin, Bytes::from(data)), &mut input)
+                .expect("encoding stdin failed");
+            self.input = Cursor::new(input.to_vec());
+        }
+    }
+
+    impl AsyncRead for TestConnection {}
+
+    impl AsyncWrite for TestConnection {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn connection(preamble: Preamble, output: Arc<Mutex<Vec<u8>>>) -> TestConnection {
+        let mut input = BytesMut::new();
+        SshEncoder::new()
+            .encode(
+                SshMsg::new(SshStream::Preamble(preamble), Bytes::new()),
+                &mut input,
+            )
+            .expect("encoding preamble failed");
+        TestConnection {
+            input: Cursor::new(input.to_vec()),
+            output,
+            reads: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Decode the frames written to a connection's output.
+    fn decode_output(output: &Mutex<Vec<u8>>) -> Vec<SshMsg> {
+        let mut written = BytesMut::from(output.lock().expect("lock poisoned").clone());
+        let mut decoder = SshDecoder::new();
+        let mut msgs = Vec::new();
+        while let Some(msg) = decoder.decode(&mut written).expect("decode failed") {
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    #[test]
+    fn close_output_flushes() {
+        let mut core = Core::new().expect("failed to create tokio core");
+        let remote = core.remote();
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        let stdio = core.run(ssh_server_mux(
+            connection(Preamble::new("repo".into()), output.clone()),
+            remote,
+            &MuxConfig::default(),
+        )).expect("mux failed");
+        assert_eq!(stdio.preamble.reponame, "repo");
+
+        core.run(stdio.stdout.clone().send(Bytes::from("out")))
+            .expect("stdout send failed");
+        core.run(stdio.stderr.clone().send(Bytes::from("err")))
+            .expect("stderr send failed");
+        core.run(stdio.close_output()).expect("close_output failed");
+
+        let msgs = decode_output(&output);
+        // stdout and stderr are merged, so their relative order isn't deterministic.
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs.contains(&SshMsg::new(SshStream::Stdout, Bytes::from("out"))));
+        assert!(msgs.contains(&SshMsg::new(SshStream::Stderr, Bytes::from("err"))));
+    }
+
+    /// Read a single large stdin frame with the given config, and return the number of reads
+    /// made from the underlying connection.
+    fn count_reads(config: &MuxConfig) -> usize {
+        let mut core = Core::new().expect("failed to create tokio core");
+        let remote = core.remote();
+
+        let data = Bytes::from(vec![b'x'; 512 * 1024]);
+        let mut conn = connection(Preamble::new("repo".into()), Arc::new(Mutex::new(Vec::new())));
+        let mut input = BytesMut::from(conn.input.into_inner());
+        SshEncoder::new()
+            .encode(SshMsg::new(SshStream::Stdin, data.clone()), &mut input)
+            .expect("encoding stdin failed");
+        conn.input = Cursor::new(input.to_vec());
+        let reads = conn.reads.clone();
+
+        let stdio = core.run(ssh_server_mux(conn, remote, config))
+            .expect("mux failed");
+        let (frame, _stdin) = core.run(stdio.stdin.into_future())
+            .map_err(|(err, _)| err)
+            .expect("reading stdin failed");
+        assert_eq!(frame, Some(data));
+
+        reads.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn read_buffer_capacity() {
+        let default_reads = count_reads(&MuxConfig::default());
+        let buffered_reads = count_reads(&MuxConfig {
+            read_buffer_capacity: Some(1024 * 1024),
+            ..Default::default()
+        });
+        // The whole input fits in the read buffer, so it's read in one go.
+        assert_eq!(buffered_reads, 1);
+        assert!(default_reads > buffered_reads);
+    }
+
+    #[test]
+    fn output_quota() {
+        let mut core = Core::new().expect("failed to create tokio core");
+        let remote = core.remote();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let config = MuxConfig {
+            byte_quota: Some(10),
+            ..Default::default()
+        };
+
+        let stdio = core.run(ssh_server_mux(
+            connection(Preamble::new("repo".into()), output.clone()),
+            remote,
+            &config,
+        )).expect("mux failed");
+
+        core.run(stdio.stdout.clone().send(Bytes::from("12345")))
+            .expect("stdout send failed");
+        core.run(stdio.stderr.clone().send(Bytes::from("1234567890")))
+            .expect("stderr send failed");
+        let err = core.run(stdio.close_output())
+            .expect_err("unexpected OK - quota exceeded");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::QuotaExceeded(10)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn input_quota() {
+        let mut core = Core::new().expect("failed to create tokio core");
+        let remote = core.remote();
+        let config = MuxConfig {
+            byte_quota: Some(10),
+            ..Default::default()
+        };
+
+        let mut conn = connection(Preamble::new("repo".into()), Arc::new(Mutex::new(Vec::new())));
+        conn.push_stdin("12345");
+        conn.push_stdin("1234567890");
+
+        let stdio = core.run(ssh_server_mux(conn, remote, &config))
+            .expect("mux failed");
+        let usage = stdio.usage.clone();
+        let (first, stdin) = core.run(stdio.stdin.into_future())
+            .map_err(|(err, _)| err)
+            .expect("reading stdin failed");
+        assert_eq!(first, Some(Bytes::from("12345")));
+        let err = core.run(stdin.into_future())
+            .map_err(|(err, _)| err)
+            .expect_err("unexpected OK - quota exceeded");
+        let err = err.into_inner()
+            .and_then(|err| err.downcast::<Compat<Error>>().ok())
+            .expect("stdin error should wrap a failure::Error")
+            .into_inner();
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::QuotaExceeded(10)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(usage.quota_exceeded());
+        assert_eq!(usage.bytes_in(), 15);
+    }
+
+    #[test]
+    fn keepalive_clamping() {
+        let config = MuxConfig::default();
+        assert_eq!(keepalive_interval(None, &config), None);
+        assert_eq!(keepalive_interval(Some(0), &config), None);
+        assert_eq!(
+            keepalive_interval(Some(1), &config),
+            Some(Duration::from_secs(MIN_KEEPALIVE_SECS))
+        );
+        assert_eq!(
+            keepalive_interval(Some(60), &config),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            keepalive_interval(Some(u64::max_value()), &config),
+            Some(Duration::from_secs(MAX_KEEPALIVE_SECS))
+        );
+
+        let config = MuxConfig {
+            min_keepalive_secs: 1,
+            max_keepalive_secs: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            keepalive_interval(Some(1), &config),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            keepalive_interval(Some(60), &config),
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn keepalive_negotiation() {
+        let mut core = Core::new().expect("failed to create tokio core");
+        let config = MuxConfig {
+            min_keepalive_secs: 1,
+            ..Default::default()
+        };
+        // Mux a connection that asks for `keepalive_secs`, leave it idle for a while and return
+        // the negotiated interval along with what was written to the client.
+        let idle = |core: &mut Core, keepalive_secs| {
+            let mut preamble = Preamble::new("repo".into());
+            preamble.keepalive_secs = keepalive_secs;
+            let output = Arc::new(Mutex::new(Vec::new()));
+            let conn = connection(preamble, output.clone());
+            let remote = core.remote();
+            let stdio = core.run(ssh_server_mux(conn, remote, &config))
+                .expect("mux failed");
+            let timeout = Timeout::new(Duration::from_millis(2500), &core.handle())
+                .expect("failed to create timeout");
+            core.run(timeout).expect("timeout failed");
+            let negotiated = stdio.preamble.keepalive_secs;
+            core.run(stdio.close_output()).expect("close_output failed");
+            (negotiated, decode_output(&output))
+        };
+
+        // The configured minimum allows a short interval, so keepalives are sent while the
+        // connection is idle.
+        let (negotiated, msgs) = idle(&mut core, Some(1));
+        assert_eq!(negotiated, Some(1));
+        assert!(!msgs.is_empty(), "no keepalives were sent");
+        for msg in msgs {
+            assert_eq!(msg, SshMsg::new(SshStream::Stderr, Bytes::new()));
+        }
+
+        let (negotiated, msgs) = idle(&mut core, Some(0));
+        assert_eq!(negotiated, Some(0));
+        assert!(msgs.is_empty(), "unexpected keepalives: {:?}", msgs);
+    }
+
+    #[test]
+    fn trace_id() {
+        let mut core = Core::new().expect("failed to create tokio core");
+        let remote = core.remote();
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        let mut preamble = Preamble::new("repo".into());
+        preamble.trace_id = Some("0123456789abcdef0123456789abcdef".into());
+        let stdio = core.run(ssh_server_mux(
+            connection(preamble, output.clone()),
+            remote.clone(),
+            &MuxConfig::default(),
+        )).expect("mux failed");
+        assert_eq!(stdio.trace_id, "0123456789abcdef0123456789abcdef");
+
+        let stdio = core.run(ssh_server_mux(
+            connection(Preamble::new("repo".into()), output.clone()),
+            remote,
+            &MuxConfig::default(),
+        )).expect("mux failed");
+        assert_eq!(stdio.trace_id.len(), 32);
+        assert!(stdio.trace_id.chars().all(|c| c.is_digit(16)));
+        assert_eq!(stdio.preamble.trace_id, Some(stdio.trace_id.clone()));
+    }
+
+    #[test]
+    fn malformed_trace_id() {
+        let mut core = Core::new().expect("failed to create tokio core");
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        let malformed = vec![
+            "abc",
+            "0123456789abcdef0123456789abcdeg",
+            "0123456789abcdef0123456789abcdef0",
+            "0123456789abcdef\n123456789abcdef",
+        ];
+        for trace_id in malformed {
+            let mut preamble = Preamble::new("repo".into());
+            preamble.trace_id = Some(trace_id.into());
+            let remote = core.remote();
+            let stdio = core.run(ssh_server_mux(
+                connection(preamble, output.clone()),
+                remote,
+                &MuxConfig::default(),
+            )).expect("mux failed");
+            assert_ne!(stdio.trace_id, trace_id);
+            assert!(is_valid_trace_id(&stdio.trace_id));
+            assert_eq!(stdio.preamble.trace_id, Some(stdio.trace_id.clone()));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rebind_with_reuse_address() {
+        use std::net;
+
+        let config = ListenerConfig {
+            reuse_address: true,
+            ..Default::default()
+        };
+        let listener = bind(&"127.0.0.1:0".parse().unwrap(), &config).expect("bind failed");
+        let addr = listener.local_addr().expect("local_addr failed");
+
+        // Close an accepted connection from the server side first, which leaves it in
+        // TIME_WAIT on the listening address.
+        let client = net::TcpStream::connect(&addr).expect("connect failed");
+        let mut incoming = listener.incoming().wait();
+        let server = incoming
+            .next()
+            .expect("no connection")
+            .expect("accept failed");
+        drop(server);
+        drop(incoming);
+        drop(client);
+
+        bind(&addr, &config).expect("rebind failed");
+    }
+}
@@ -38,6 +38,7 @@ extern crate slog_term;
 
 extern crate dns_lookup;
 extern crate lz4;
+extern crate net2;
 #[macro_use]
 extern crate maplit;
 
@@ -71,6 +72,7 @@ extern crate tracing_fb303;
 extern crate upload_trace;
 
 mod errors;
+mod limiter;
 mod listener;
 mod monitoring;
 mod repo;
@@ -88,10 +90,10 @@ use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
 use failure::SlogKVError;
-use futures::{Future, IntoFuture, Sink, Stream};
+use futures::{stream, Future, IntoFuture, Sink, Stream};
 use futures::sink::Wait;
 use futures::sync::mpsc;
-use futures_ext::{asynchronize, FutureExt};
+use futures_ext::{asynchronize, FutureExt, StreamExt};
 use futures_stats::Timed;
 use tokio::util::FutureExt as TokioFutureExt;
 
@@ -116,7 +118,8 @@ use metaconfig::repoconfig::RepoConfig;
 
 use errors::*;
 
-use listener::{ssh_server_mux, Stdio};
+use limiter::{ConnectionGuard, PerUserLimiter};
+use listener::{ssh_server_mux, ListenerConfig, MuxConfig, Stdio};
 use monitoring::{ReadyHandle, ReadyState, ReadyStateBuilder};
 
 struct SenderBytesWrite {
@@ -164,6 +167,10 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
 
             -p, --thrift_port [PORT] 'if provided the thrift server will start on this port'
 
+                          --max-connections-per-user [N]         'maximum number of connections each user may have open'
+
+                          --max-bytes-per-connection [N]         'maximum number of bytes each connection may transfer'
+
             -d, --debug                                          'print debug level output'
         "#,
         )
@@ -242,6 +249,8 @@ fn start_repo_listeners<I>(
     repos: I,
     root_log: &Logger,
     sockname: &str,
+    limiter: Option<PerUserLimiter>,
+    mux_config: MuxConfig,
 ) -> Result<(Vec<JoinHandle<!>>, ReadyState)>
 where
     I: IntoIterator<Item = (String, RepoConfig)>,
@@ -281,7 +290,7 @@ where
         .name(format!("connection_acceptor"))
         .spawn({
             let root_log = root_log.clone();
-            move || connection_acceptor(&sockname, root_log, repo_senders)
+            move || connection_acceptor(&sockname, root_log, repo_senders, limiter, mux_config)
         })
         .map_err(Error::from);
 
@@ -306,11 +315,14 @@ where
 fn connection_acceptor(
     sockname: &str,
     root_log: Logger,
-    repo_senders: HashMap<String, mpsc::Sender<(Stdio, SocketAddr)>>,
+    repo_senders: HashMap<String, mpsc::Sender<(Stdio, SocketAddr, Option<ConnectionGuard>)>>,
+    limiter: Option<PerUserLimiter>,
+    mux_config: MuxConfig,
 ) -> ! {
     let mut core = tokio_core::reactor::Core::new().expect("failed to create tokio core");
     let remote = core.remote();
-    let connection_acceptor = listener::listener(sockname)
+    let handle = core.handle();
+    let connection_acceptor = listener::listener(sockname, &ListenerConfig::default())
         .expect("failed to create listener")
         .map_err(Error::from)
         .and_then({
@@ -323,7 +335,7 @@ fn connection_acceptor(
                         return Ok(None).into_future().boxify();
                     }
                 };
-                ssh_server_mux(sock, remote.clone())
+                ssh_server_mux(sock, remote.clone(), &mux_config)
                     .map(move |stdio| Some((stdio, addr)))
                     .or_else({
                         let root_log = root_log.clone();
@@ -340,10 +352,34 @@ fn connection_acceptor(
                 return Ok(()).into_future().boxify();
             }
             let (stdio, addr) = maybe_stdio.unwrap();
+            let acquired = limiter.as_ref().map(|limiter| limiter.acquire(&stdio.preamble));
+            let guard = match acquired {
+                None => None,
+                Some(Ok(guard)) => Some(guard),
+                Some(Err(err)) => {
+                    error!(root_log, "Rejecting connection: {}", err);
+                    // Tell the client why, and flush that before the connection is closed. This
+                    // is spawned so that a slow client doesn't hold up accepting connections.
+                    let root_log = root_log.clone();
+                    handle.spawn(
+                        stdio
+                            .stderr
+                            .clone()
+                            .send(Bytes::from(format!("{}\n", err)))
+                            .map_err(|_| Error::from(ErrorKind::OutputDropped))
+                            .and_then(move |_stderr| stdio.close_output())
+                            .or_else(move |err| {
+                                warn!(root_log, "failed to flush connection output: {}", err);
+                                Ok(())
+                            }),
+                    );
+                    return Ok(()).into_future().boxify();
+                }
+            };
             match repo_senders.get(&stdio.preamble.reponame) {
                 Some(sender) => sender
                     .clone()
-                    .send((stdio, addr))
+                    .send((stdio, addr, guard))
                     .map(|_| ())
                     .or_else({
                         let root_log = root_log.clone();
@@ -376,7 +412,7 @@ fn repo_listen(
     config: RepoConfig,
     root_log: Logger,
     ready_handle: ReadyHandle,
-    input_stream: mpsc::Receiver<(Stdio, SocketAddr)>,
+    input_stream: mpsc::Receiver<(Stdio, SocketAddr, Option<ConnectionGuard>)>,
 ) -> ! {
     let mut core = tokio_core::reactor::Core::new().expect("failed to create tokio core");
 
@@ -405,21 +441,18 @@ fn repo_listen(
             });
     let initial_warmup = ready_handle.wait_for(initial_warmup);
 
-    let server = input_stream.for_each(move |(stdio, addr)| {
-        // Have a connection. Extract std{in,out,err} streams for socket
-        let Stdio {
-            stdin,
-            stdout,
-            stderr,
-            preamble,
-        } = stdio;
+    let server = input_stream.for_each(move |(mut stdio, addr, guard)| {
+        // Have a connection. Take stdin out of it; the rest is kept so that the output can be
+        // flushed with `close_output` once the connection is finished.
+        let stdin = mem::replace(&mut stdio.stdin, stream::empty().boxify());
+        let trace_id = stdio.trace_id.clone();
 
         let session_uuid = uuid::Uuid::new_v4();
         let wireproto_calls = Arc::new(Mutex::new(Vec::new()));
         let trace = TraceContext::new(session_uuid, Instant::now());
 
         let stderr_write = SenderBytesWrite {
-            chan: stderr.clone().wait(),
+            chan: stdio.stderr.clone().wait(),
         };
         let drain = slog_term::PlainSyncDecorator::new(stderr_write);
         let drain = slog_term::FullFormat::new(drain).build();
@@ -427,7 +460,10 @@ fn repo_listen(
             "remote".into() => hashset!["true".into()],
         });
         let drain = slog::Duplicate::new(drain, listen_log.clone()).ignore_res();
-        let conn_log = Logger::root(drain, o!("session_uuid" => format!("{}", session_uuid)));
+        let conn_log = Logger::root(
+            drain,
+            o!("session_uuid" => format!("{}", session_uuid), "trace_id" => trace_id.clone()),
+        );
 
         let mut scuba_logger = {
             let client_hostname = match getnameinfo(&addr, 0) {
@@ -443,6 +479,7 @@ fn repo_listen(
             let mut scuba_logger = scuba_logger.clone();
             scuba_logger
                 .add("session_uuid", format!("{}", session_uuid))
+                .add("trace_id", trace_id)
                 .add("client_hostname", client_hostname);
             scuba_logger
         };
@@ -452,7 +489,13 @@ fn repo_listen(
         // Construct a hg protocol handler
         let proto_handler = HgProtoHandler::new(
             stdin,
-            repo::RepoClient::new(repo.clone(), conn_log.clone(), scuba_logger.clone(), trace),
+            repo::RepoClient::new(
+                repo.clone(),
+                conn_log.clone(),
+                scuba_logger.clone(),
+                trace,
+                stdio.usage.clone(),
+            ),
             sshproto::HgSshCommandDecode,
             sshproto::HgSshCommandEncode,
             &conn_log,
@@ -460,14 +503,14 @@ fn repo_listen(
         );
 
         // send responses back
-        let endres = if preamble.reponame == reponame {
+        let endres = if stdio.preamble.reponame == reponame {
             proto_handler
                 .map_err(Error::from)
-                .forward(stdout)
+                .forward(stdio.stdout.clone())
                 .map(|_| ())
                 .boxify()
         } else {
-            Err(ErrorKind::IncorrectRepoName(preamble.reponame).into())
+            Err(ErrorKind::IncorrectRepoName(stdio.preamble.reponame.clone()).into())
                 .into_future()
                 .boxify()
         };
@@ -479,6 +522,7 @@ fn repo_listen(
         // TODO: (stash) T30523706 seems to leave the client hanging?
 
         // Don't wait for more that 15 mins for a request
+        let usage = stdio.usage.clone();
         let endres = endres
             .deadline(Instant::now() + Duration::from_secs(900))
             .timed(move |stats, result| {
@@ -487,7 +531,10 @@ fn repo_listen(
 
                 scuba_logger
                     .add_stats(&stats)
-                    .add("wireproto_commands", wireproto_calls);
+                    .add("wireproto_commands", wireproto_calls)
+                    .add("commands", usage.commands())
+                    .add("bytes_in", usage.bytes_in())
+                    .add("bytes_out", usage.bytes_out());
 
                 match result {
                     Ok(_) => scuba_logger.log_with_msg("Request finished - Success", None),
@@ -523,7 +570,20 @@ fn repo_listen(
 
         // Make this double async.
         // TODO(stash, luk) is this really necessary?
-        handle.spawn(asynchronize(move || endres).then(|_| Ok(())));
+        // Once the connection is finished, however it ends, flush what's left of its output.
+        // The connection counts against its user's limit until then.
+        let listen_log = listen_log.clone();
+        handle.spawn(
+            asynchronize(move || endres)
+                .then(move |_| stdio.close_output())
+                .then(move |res| {
+                    if let Err(err) = res {
+                        warn!(listen_log, "failed to flush connection output: {}", err);
+                    }
+                    drop(guard);
+                    Ok(())
+                }),
+        );
 
         Ok(())
     });
@@ -547,12 +607,25 @@ fn main() {
         let stats_aggregation = monitoring::start_stats()?;
 
         let config = get_config(root_log, &matches)?;
+        let limiter = match matches.value_of("max-connections-per-user") {
+            Some(max) => Some(PerUserLimiter::new(max.parse()?)),
+            None => None,
+        };
+        let mux_config = MuxConfig {
+            byte_quota: match matches.value_of("max-bytes-per-connection") {
+                Some(max) => Some(max.parse()?),
+                None => None,
+            },
+            ..Default::default()
+        };
         let (repo_listeners, ready) = start_repo_listeners(
             config.repos.into_iter(),
             root_log,
             matches
                 .value_of("listening-host-port")
                 .expect("listening path must be specified"),
+            limiter,
+            mux_config,
         )?;
 
         tracing_fb303::register();
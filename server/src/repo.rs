@@ -48,6 +48,7 @@ use hgproto::{self, GetbundleArgs, GettreepackArgs, HgCommandRes, HgCommands};
 use blobrepo::BlobRepo;
 
 use errors::*;
+use listener::ResourceUsage;
 
 use repoinfo::RepoGenCache;
 use revset::DifferenceOfUnionsOfAncestorsNodeStream;
@@ -284,6 +285,8 @@ pub struct RepoClient {
     logger: Logger,
     scuba_logger: ScubaSampleBuilder,
     trace: TraceContext,
+    // Resource usage of the connection this client serves. Every command is recorded in it.
+    usage: ResourceUsage,
 }
 
 impl RepoClient {
@@ -292,12 +295,14 @@ impl RepoClient {
         logger: Logger,
         scuba_logger: ScubaSampleBuilder,
         trace: TraceContext,
+        usage: ResourceUsage,
     ) -> Self {
         RepoClient {
             repo,
             logger,
             scuba_logger,
             trace,
+            usage,
         }
     }
 
@@ -307,6 +312,9 @@ impl RepoClient {
     }
 
     fn scuba_logger(&self, op: &str, args: Option<String>) -> ScubaSampleBuilder {
+        // This is called at the start of every command.
+        self.usage.record_command();
+
         let mut scuba_logger = self.scuba_logger.clone();
 
         scuba_logger.add("command", op);
@@ -20,7 +20,9 @@ use tokio_io::codec::{Decoder, Encoder};
 
 use netstring::{NetstringDecoder, NetstringEncoder};
 
-// Multiplex stdin/out/err over a single stream using netstring as framing
+// Multiplex stdin/out/err over a single stream using netstring as framing. Each netstring
+// starts with a one byte stream tag, followed by the payload. An empty payload is a valid
+// message (a netstring containing just the tag), and is distinct from the end of the stream.
 #[derive(Debug)]
 pub struct SshDecoder(NetstringDecoder);
 
@@ -34,6 +36,15 @@ pub struct Preamble {
     pub reponame: String,
     // Additional information that will be send to the server. Examples: user/host identity.
     pub misc: HashMap<String, String>,
+    // Id used to trace this connection across services, as a hex string. If the client doesn't
+    // supply one, the server generates it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    // How often the client would like keepalive frames (empty stderr messages) to be sent while
+    // the connection is otherwise idle, in seconds. 0 asks for no keepalives. The server may
+    // adjust this to fit its limits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive_secs: Option<u64>,
 }
 
 impl Preamble {
@@ -41,6 +52,8 @@ impl Preamble {
         Self {
             reponame,
             misc: HashMap::new(),
+            trace_id: None,
+            keepalive_secs: None,
         }
     }
 }
@@ -95,7 +108,13 @@ impl Decoder for SshDecoder {
     fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<SshMsg>> {
         if let Some(mut data) = self.0.decode(buf)? {
             if data.len() == 0 {
-                return Ok(None);
+                // Every frame has a stream tag, even if its payload is empty. Returning
+                // Ok(None) here would look like a partial frame to the caller, and silently
+                // drop the frame.
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "ssh frame without a stream tag",
+                ));
             }
             match data.split_to(1)[0] {
                 0 => Ok(Some(SshMsg(SshStream::Stdin, data.freeze()))),
@@ -340,4 +359,55 @@ mod test {
             Err(_err) => (),
         }
     }
+
+    #[test]
+    fn preamble_without_trace_id() {
+        // Preambles from clients that predate trace ids must still decode.
+        let preamble: Preamble = serde_json::from_str(r#"{"reponame":"repo","misc":{}}"#)
+            .expect("decoding preamble failed");
+        assert_eq!(preamble, Preamble::new("repo".into()));
+
+        let mut preamble = Preamble::new("repo".into());
+        preamble.trace_id = Some("abcd".into());
+        let encoded = serde_json::to_string(&preamble).expect("encoding preamble failed");
+        let decoded: Preamble = serde_json::from_str(&encoded).expect("decoding preamble failed");
+        assert_eq!(decoded, preamble);
+    }
+
+    #[test]
+    fn roundtrip_empty_payload() {
+        let mut buf = BytesMut::with_capacity(1024);
+        let mut encoder = SshEncoder::new();
+        let msgs = vec![
+            SshMsg::new(Stdout, b"X".bytes()),
+            SshMsg::new(Stdout, b"".bytes()),
+            SshMsg::new(Stdout, b"Y".bytes()),
+        ];
+
+        for msg in msgs.clone() {
+            encoder.encode(msg, &mut buf).expect("encode failed");
+        }
+        assert_eq!(buf.as_ref(), b"2:\x01X,1:\x01,2:\x01Y,");
+
+        let mut decoder = SshDecoder::new();
+        let mut decoded = vec![];
+        while let Some(msg) = decoder.decode(&mut buf).expect("decode failed") {
+            decoded.push(msg);
+        }
+        assert_eq!(decoded, msgs);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_untagged() {
+        let mut buf = BytesMut::with_capacity(1024);
+        buf.put_slice(b"0:,");
+
+        let mut decoder = SshDecoder::new();
+
+        match decoder.decode(&mut buf) {
+            Ok(bad) => panic!("unexpected success: {:?}", bad),
+            Err(_err) => (),
+        }
+    }
 }
@@ -29,10 +29,12 @@ extern crate mercurial_types_mocks;
 pub mod errors;
 mod manifest_vfs;
 mod node;
+mod pathspec;
 mod tree;
 
 pub use manifest_vfs::{vfs_from_manifest, ManifestVfsDir, ManifestVfsFile};
 pub use node::{VfsDir, VfsFile, VfsNode, VfsWalker};
+pub use pathspec::expand_pathspec;
 
 #[cfg(test)]
 mod test;
@@ -0,0 +1,140 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::VecDeque;
+
+use futures::{stream, Future, Stream};
+use futures_ext::{BoxStream, StreamExt};
+
+use mononoke_types::MPath;
+
+use errors::*;
+use node::{VfsDir, VfsFile, VfsNode, VfsWalker};
+
+/// Expand a path-spec, as typed by a user, against the Vfs rooted at `root`. If `spec` names a
+/// directory, every file beneath it is streamed; if it names a file, just that file is. A spec
+/// that doesn't exist in the Vfs expands to nothing, and an empty spec (or one consisting only
+/// of slashes) names the root.
+///
+/// Files in a directory are streamed in sorted order, but directories are expanded breadth
+/// first, so the output as a whole is not sorted.
+pub fn expand_pathspec<TDir, TFile>(
+    root: VfsNode<TDir, TFile>,
+    spec: &[u8],
+) -> BoxStream<MPath, Error>
+where
+    TDir: VfsDir<TFile = TFile>,
+    TFile: VfsFile<TDir = TDir>,
+{
+    let path = if spec.iter().all(|c| *c == b'/') {
+        None
+    } else {
+        match MPath::new(spec) {
+            Ok(path) => Some(path),
+            Err(err) => return stream::once(Err(err)).boxify(),
+        }
+    };
+
+    VfsWalker::new(root, MPath::into_iter_opt(path.clone()))
+        .walk()
+        .map(Some)
+        .or_else(|err| match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::PathDoesNotExist(..)) => Ok(None),
+            Ok(err) => Err(err.into()),
+            Err(err) => Err(err),
+        })
+        .map(move |node| match node {
+            None => stream::empty().boxify(),
+            Some(VfsNode::File(_)) => stream::iter_ok(path).boxify(),
+            Some(VfsNode::Dir(dir)) => walk_files(dir, path),
+        })
+        .flatten_stream()
+        .boxify()
+}
+
+/// Stream the paths of all files beneath `dir`, which is at `path`.
+fn walk_files<TDir, TFile>(dir: TDir, path: Option<MPath>) -> BoxStream<MPath, Error>
+where
+    TDir: VfsDir<TFile = TFile>,
+    TFile: VfsFile<TDir = TDir>,
+{
+    stream::unfold(VecDeque::from(vec![(dir, path)]), |mut pending| {
+        pending.pop_back().map(|(dir, path)| {
+            let mut elements: Vec<_> = dir.read().into_iter().cloned().collect();
+            elements.sort();
+
+            let mut files = vec![];
+            for element in elements {
+                let child_path = MPath::join_opt_element(path.as_ref(), &element);
+                match dir.step(&element) {
+                    Some(VfsNode::File(_)) => files.push(child_path),
+                    Some(VfsNode::Dir(child)) => pending.push_front((child, Some(child_path))),
+                    None => (),
+                }
+            }
+            Ok::<_, Error>((stream::iter_ok::<_, Error>(files), pending))
+        })
+    }).flatten()
+        .boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mercurial_types::FileType;
+    use mercurial_types_mocks::manifest::MockManifest;
+
+    use manifest_vfs::vfs_from_manifest;
+
+    fn expand(spec: &str) -> Vec<MPath> {
+        let paths = btreemap! {
+            "a/b" => (FileType::Regular, ""),
+            "a/ab" => (FileType::Regular, ""),
+            "c/d/e" => (FileType::Regular, ""),
+            "c/d/da" => (FileType::Regular, ""),
+            "c/ca/afsd" => (FileType::Regular, ""),
+            "f" => (FileType::Regular, ""),
+        };
+        let root_manifest = MockManifest::from_paths(paths).expect("invalid manifest?");
+        let vfs = vfs_from_manifest(&root_manifest)
+            .wait()
+            .expect("failed to get vfs");
+
+        let mut files = expand_pathspec(vfs.into_node(), spec.as_bytes())
+            .collect()
+            .wait()
+            .expect("failed to expand pathspec");
+        files.sort();
+        files
+    }
+
+    fn paths(paths: Vec<&str>) -> Vec<MPath> {
+        paths.into_iter().map(|p| MPath::new(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_expand_dir() {
+        assert_eq!(expand("c"), paths(vec!["c/ca/afsd", "c/d/da", "c/d/e"]));
+        assert_eq!(expand("c/d/"), paths(vec!["c/d/da", "c/d/e"]));
+        assert_eq!(
+            expand(""),
+            paths(vec!["a/ab", "a/b", "c/ca/afsd", "c/d/da", "c/d/e", "f"])
+        );
+    }
+
+    #[test]
+    fn test_expand_file() {
+        assert_eq!(expand("a/b"), paths(vec!["a/b"]));
+        assert_eq!(expand("f"), paths(vec!["f"]));
+    }
+
+    #[test]
+    fn test_expand_missing() {
+        assert!(expand("x").is_empty());
+        assert!(expand("a/x/y").is_empty());
+    }
+}